@@ -0,0 +1,367 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+pub trait AudioSink{
+    fn sample_rate(&self) -> u32;
+    fn write(&mut self, samples: &[f32]);
+}
+
+pub struct SquareWave{
+    frequency: f32,
+    volume: f32,
+    sample_rate: u32,
+    phase: f32,
+}
+
+impl SquareWave{
+    pub fn new(sample_rate: u32) -> Self{
+        Self{
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32){
+        self.frequency = frequency;
+    }
+
+    pub fn set_volume(&mut self, volume: f32){
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn fill(&mut self, buffer: &mut [f32]){
+        let step = self.frequency / self.sample_rate as f32;
+        for sample in buffer.iter_mut(){
+            *sample = if self.phase < 0.5 { self.volume } else { -self.volume };
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+// XO-CHIP 1-bit audio: 128 bits played back at 4000 * 2^((pitch - 64) / 48) Hz.
+pub struct PatternWave{
+    pattern: [u8; 16],
+    rate: f32,
+    volume: f32,
+    sample_rate: u32,
+    position: f32,
+}
+
+impl PatternWave{
+    pub fn new(pattern: [u8; 16], pitch: u8, volume: f32, sample_rate: u32) -> Self{
+        Self{
+            pattern,
+            rate: 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0),
+            volume,
+            sample_rate,
+            position: 0.0,
+        }
+    }
+
+    pub fn fill(&mut self, buffer: &mut [f32]){
+        let step = self.rate / self.sample_rate as f32;
+        for sample in buffer.iter_mut(){
+            let bit = self.position as usize;
+            let lit = (self.pattern[bit / 8] >> (7 - bit % 8)) & 1 == 1;
+            *sample = if lit { self.volume } else { -self.volume };
+            self.position = (self.position + step) % 128.0;
+        }
+    }
+}
+
+// Turns the once-per-60Hz-tick beep state into samples for a sink. Each tick
+// covers 1/60 s of real time divided by the speed, so the sink is fed at its
+// own rate however fast frames run.
+pub struct Beeper{
+    wave: SquareWave,
+    pattern: Option<PatternWave>,
+    sink: Box<dyn AudioSink>,
+    buffer: Vec<f32>,
+    samples_per_tick: f64,
+    // Fraction of a sample carried over to the next tick.
+    pending: f64,
+}
+
+impl Beeper{
+    pub fn new(sink: Box<dyn AudioSink>) -> Self{
+        let sample_rate = sink.sample_rate();
+        Self{
+            wave: SquareWave::new(sample_rate),
+            pattern: None,
+            sink,
+            buffer: Vec::new(),
+            samples_per_tick: sample_rate as f64 / 60.0,
+            pending: 0.0,
+        }
+    }
+
+    pub fn wave_mut(&mut self) -> &mut SquareWave{
+        &mut self.wave
+    }
+
+    // The emulation speed multiplier, measured when uncapped.
+    pub fn set_speed(&mut self, multiplier: f64){
+        if multiplier > 0.0 && multiplier.is_finite(){
+            self.samples_per_tick = self.wave.sample_rate as f64 / 60.0 / multiplier;
+        }
+    }
+
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8){
+        let Some(pattern) = pattern else {
+            self.pattern = None;
+            return;
+        };
+        let position = self.pattern.as_ref().map_or(0.0, |wave| wave.position);
+        let mut wave = PatternWave::new(pattern, pitch, self.wave.volume, self.wave.sample_rate);
+        wave.position = position;
+        self.pattern = Some(wave);
+    }
+
+    pub fn render_tick(&mut self, on: bool){
+        self.pending += self.samples_per_tick;
+        let len = self.pending as usize;
+        self.pending -= len as f64;
+        self.buffer.resize(len, 0.0);
+
+        if !on{
+            self.buffer.fill(0.0);
+        } else if let Some(pattern) = &mut self.pattern{
+            pattern.fill(&mut self.buffer);
+        } else {
+            self.wave.fill(&mut self.buffer);
+        }
+        self.sink.write(&self.buffer);
+    }
+}
+
+#[derive(Default)]
+pub struct NullSink{
+    pub samples_written: usize,
+}
+
+impl AudioSink for NullSink{
+    fn sample_rate(&self) -> u32{
+        DEFAULT_SAMPLE_RATE
+    }
+
+    fn write(&mut self, samples: &[f32]){
+        self.samples_written += samples.len();
+    }
+}
+
+// 16-bit mono PCM. The RIFF sizes are patched in when the sink is dropped.
+pub struct WavSink{
+    writer: BufWriter<File>,
+    sample_rate: u32,
+    data_len: u32,
+}
+
+impl WavSink{
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self>{
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_wav_header(&mut writer, sample_rate, 0)?;
+        Ok(Self{writer, sample_rate, data_len: 0})
+    }
+
+    pub fn finish(&mut self) -> io::Result<()>{
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.writer, self.sample_rate, self.data_len)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+impl AudioSink for WavSink{
+    fn sample_rate(&self) -> u32{
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples: &[f32]){
+        for sample in samples{
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            if self.writer.write_all(&value.to_le_bytes()).is_err(){
+                return;
+            }
+            self.data_len += 2;
+        }
+    }
+}
+
+impl Drop for WavSink{
+    fn drop(&mut self){
+        let _ = self.finish();
+    }
+}
+
+fn write_wav_header<W: Write>(writer: &mut W, sample_rate: u32, data_len: u32) -> io::Result<()>{
+    let channels: u16 = 1;
+    let bits_per_sample: u16 = 16;
+    let block_align = channels * bits_per_sample / 8;
+    let byte_rate = sample_rate * block_align as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bits_per_sample.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())
+}
+
+#[cfg(feature = "audio")]
+pub use self::device::DeviceSink;
+
+#[cfg(feature = "audio")]
+mod device{
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{FromSample, SampleFormat, SizedSample, StreamConfig};
+
+    use super::AudioSink;
+
+    // Plays samples on the default output device. Samples are queued from
+    // the emulator thread and drained by the cpal callback.
+    pub struct DeviceSink{
+        _stream: cpal::Stream,
+        queue: Arc<Mutex<VecDeque<f32>>>,
+        sample_rate: u32,
+    }
+
+    impl DeviceSink{
+        pub fn open() -> Option<Self>{
+            let device = cpal::default_host().default_output_device()?;
+            let config = device.default_output_config().ok()?;
+            let sample_rate = config.sample_rate().0;
+            let format = config.sample_format();
+            let config = config.config();
+
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+            let stream = match format{
+                SampleFormat::F32 => build_stream::<f32>(&device, &config, &queue),
+                SampleFormat::F64 => build_stream::<f64>(&device, &config, &queue),
+                SampleFormat::I8 => build_stream::<i8>(&device, &config, &queue),
+                SampleFormat::I16 => build_stream::<i16>(&device, &config, &queue),
+                SampleFormat::I32 => build_stream::<i32>(&device, &config, &queue),
+                SampleFormat::I64 => build_stream::<i64>(&device, &config, &queue),
+                SampleFormat::U8 => build_stream::<u8>(&device, &config, &queue),
+                SampleFormat::U16 => build_stream::<u16>(&device, &config, &queue),
+                SampleFormat::U32 => build_stream::<u32>(&device, &config, &queue),
+                SampleFormat::U64 => build_stream::<u64>(&device, &config, &queue),
+                format => {
+                    eprintln!("warning: unsupported audio sample format {format}");
+                    return None;
+                }
+            }.ok()?;
+            stream.play().ok()?;
+
+            Some(Self{_stream: stream, queue, sample_rate})
+        }
+    }
+
+    // Mono samples are converted to the device's format and copied to every channel.
+    fn build_stream<T>(device: &cpal::Device, config: &StreamConfig, queue: &Arc<Mutex<VecDeque<f32>>>) -> Result<cpal::Stream, cpal::BuildStreamError>
+    where
+        T: SizedSample + FromSample<f32>,
+    {
+        let channels = config.channels as usize;
+        let queue = queue.clone();
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut queue = queue.lock().unwrap();
+                for frame in data.chunks_mut(channels){
+                    let sample = queue.pop_front().unwrap_or(0.0);
+                    frame.fill(T::from_sample(sample));
+                }
+            },
+            |err| eprintln!("audio error: {err}"),
+            None,
+        )
+    }
+
+    impl AudioSink for DeviceSink{
+        fn sample_rate(&self) -> u32{
+            self.sample_rate
+        }
+
+        fn write(&mut self, samples: &[f32]){
+            let mut queue = self.queue.lock().unwrap();
+            // Keep latency bounded if the device consumes slower than we produce.
+            let max_len = self.sample_rate as usize / 10;
+            queue.extend(samples);
+            let excess = queue.len().saturating_sub(max_len);
+            queue.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_wav(name: &str) -> PathBuf{
+        std::env::temp_dir().join(format!("pico8-{}-{name}.wav", std::process::id()))
+    }
+
+    // Runs the ticks through a Beeper into a WAV file and returns the samples.
+    fn record(name: &str, sample_rate: u32, speed: f64, ticks: &[bool]) -> Vec<i16>{
+        let path = temp_wav(name);
+        let mut beeper = Beeper::new(Box::new(WavSink::create(&path, sample_rate).unwrap()));
+        beeper.set_speed(speed);
+        for &on in ticks{
+            beeper.render_tick(on);
+        }
+        drop(beeper);
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let data_len = u32::from_le_bytes(bytes[40..44].try_into().unwrap()) as usize;
+        assert_eq!(bytes.len(), 44 + data_len);
+        bytes[44..].chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect()
+    }
+
+    #[test]
+    fn null_sink_counts_samples(){
+        let mut sink = NullSink::default();
+        sink.write(&[0.0; 735]);
+        sink.write(&[0.5; 10]);
+        assert_eq!(sink.samples_written, 745);
+    }
+
+    #[test]
+    fn one_tick_is_a_sixtieth_of_a_second(){
+        let samples = record("tick", DEFAULT_SAMPLE_RATE, 1.0, &[true, false, true]);
+        assert_eq!(samples.len(), 3 * 735);
+        assert!(samples[..735].iter().any(|&sample| sample != 0));
+        assert!(samples[735..1470].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn speed_scales_samples_per_tick(){
+        assert_eq!(record("fast", DEFAULT_SAMPLE_RATE, 2.0, &[true; 4]).len(), 2 * 735);
+        assert_eq!(record("slow", DEFAULT_SAMPLE_RATE, 0.5, &[true; 1]).len(), 2 * 735);
+    }
+
+    #[test]
+    fn fractional_samples_carry_over(){
+        // 1000 / 60 is 16.67 samples per tick.
+        assert_eq!(record("carry", 1000, 1.0, &[false; 3]).len(), 50);
+    }
+}
@@ -1,7 +1,9 @@
 pub trait Backend{
-    fn draw_frame(&mut self, framebuffer: &[u8; 64 * 32]);
+    fn draw_frame(&mut self, framebuffer: &[u8], width: usize, height: usize);
     fn poll_key(&mut self, key: Keys) -> bool;
     fn wait_for_key(&mut self);
+    fn set_beep(&mut self, on: bool);
+    fn set_audio_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8);
 }
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+pub const DEFAULT_OR_FRAMES: usize = 3;
+// Past a quarter second lit pixels turn into smears rather than hiding flicker.
+pub const MAX_OR_FRAMES: u32 = 16;
+// Share of the previous brightness a phosphor pixel keeps each frame.
+const PHOSPHOR_DECAY: f32 = 0.6;
+
+// Ways of hiding the flicker of XOR drawn sprites that are erased and redrawn
+// every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode{
+    Off,
+    // Pixels light up at once and fade out over a few frames like a CRT.
+    Phosphor,
+    // Each pixel is the average color of the last two frames.
+    Blend,
+    // A pixel is lit if it was lit in any of the last few frames.
+    Or,
+}
+
+impl FilterMode{
+    pub const ALL: [FilterMode; 4] = [
+        FilterMode::Off,
+        FilterMode::Phosphor,
+        FilterMode::Blend,
+        FilterMode::Or,
+    ];
+
+    pub fn name(&self) -> &'static str{
+        match self{
+            FilterMode::Off => "off",
+            FilterMode::Phosphor => "phosphor",
+            FilterMode::Blend => "blend",
+            FilterMode::Or => "or",
+        }
+    }
+
+    pub fn next(&self) -> Self{
+        let idx = Self::ALL.iter().position(|mode| mode == self).unwrap();
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+}
+
+impl fmt::Display for FilterMode{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for FilterMode{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        FilterMode::ALL
+            .into_iter()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = FilterMode::ALL.iter().map(|mode| mode.name()).collect();
+                format!("unknown filter `{s}`, expected one of: {}", names.join(", "))
+            })
+    }
+}
+
+// Turns framebuffers of plane indices into RGBA, keeping the history the
+// filters need. Expects to be fed once per presented frame.
+pub struct FrameFilter{
+    mode: FilterMode,
+    or_frames: usize,
+    history: VecDeque<Vec<u8>>,
+    glow: Vec<[f32; 3]>,
+}
+
+impl FrameFilter{
+    pub fn new(mode: FilterMode, or_frames: usize) -> Self{
+        Self{
+            mode,
+            or_frames: or_frames.max(1),
+            history: VecDeque::new(),
+            glow: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> FilterMode{
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: FilterMode){
+        self.mode = mode;
+        self.reset();
+    }
+
+    // Whether the output changes from frame to frame even if the screen doesn't.
+    pub fn is_active(&self) -> bool{
+        self.mode != FilterMode::Off
+    }
+
+    pub fn reset(&mut self){
+        self.history.clear();
+        self.glow.clear();
+    }
+
+    pub fn apply(&mut self, framebuffer: &[u8], colors: &[[u8; 4]; 4], rgba: &mut [u8]){
+        // A resolution switch makes the old frames meaningless.
+        if self.history.back().is_some_and(|frame| frame.len() != framebuffer.len()){
+            self.reset();
+        }
+        if self.history.len() == self.or_frames.max(2){
+            self.history.pop_front();
+        }
+        self.history.push_back(framebuffer.to_vec());
+
+        let color = |pixel: u8| colors[pixel as usize & 0x3];
+        let pixels = rgba.chunks_exact_mut(4).zip(framebuffer);
+        match self.mode{
+            FilterMode::Off => {
+                for (out, &pixel) in pixels{
+                    out.copy_from_slice(&color(pixel));
+                }
+            },
+            FilterMode::Phosphor => {
+                if self.glow.len() != framebuffer.len(){
+                    self.glow = framebuffer.iter().map(|&pixel| {
+                        let [r, g, b, _] = color(pixel);
+                        [r as f32, g as f32, b as f32]
+                    }).collect();
+                }
+                for ((out, &pixel), glow) in pixels.zip(&mut self.glow){
+                    let target = color(pixel);
+                    for channel in 0..3{
+                        let target_channel = target[channel] as f32;
+                        // Brightening is instant, dimming decays towards the new color.
+                        glow[channel] = if target_channel >= glow[channel]{
+                            target_channel
+                        } else {
+                            target_channel + (glow[channel] - target_channel) * PHOSPHOR_DECAY
+                        };
+                        out[channel] = glow[channel].round() as u8;
+                    }
+                    out[3] = target[3];
+                }
+            },
+            FilterMode::Blend => {
+                let previous = &self.history[self.history.len().saturating_sub(2)];
+                for ((out, &pixel), &old) in pixels.zip(previous){
+                    let (new, old) = (color(pixel), color(old));
+                    for channel in 0..4{
+                        out[channel] = ((new[channel] as u16 + old[channel] as u16) / 2) as u8;
+                    }
+                }
+            },
+            FilterMode::Or => {
+                let start = self.history.len().saturating_sub(self.or_frames);
+                for (idx, out) in rgba.chunks_exact_mut(4).take(framebuffer.len()).enumerate(){
+                    let pixel = self.history.range(start..).fold(0, |lit, frame| lit | frame[idx]);
+                    out.copy_from_slice(&color(pixel));
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const COLORS: [[u8; 4]; 4] = [
+        [0, 0, 0, 255],
+        [200, 100, 50, 255],
+        [0, 0, 200, 255],
+        [255, 255, 255, 255],
+    ];
+
+    // Feeds the frames of two pixels each and returns the RGBA of every output.
+    fn run(mut filter: FrameFilter, frames: &[[u8; 2]]) -> Vec<[[u8; 4]; 2]>{
+        frames.iter().map(|frame| {
+            let mut rgba = [0; 8];
+            filter.apply(frame, &COLORS, &mut rgba);
+            [rgba[..4].try_into().unwrap(), rgba[4..].try_into().unwrap()]
+        }).collect()
+    }
+
+    #[test]
+    fn phosphor_lights_at_once_and_fades(){
+        let output = run(FrameFilter::new(FilterMode::Phosphor, DEFAULT_OR_FRAMES), &[[1, 0], [0, 0], [0, 0], [1, 0]]);
+        assert_eq!(output[0], [[200, 100, 50, 255], [0, 0, 0, 255]]);
+        assert_eq!(output[1][0], [120, 60, 30, 255]);
+        assert_eq!(output[2][0], [72, 36, 18, 255]);
+        assert_eq!(output[3][0], [200, 100, 50, 255]);
+    }
+
+    #[test]
+    fn blend_averages_two_frames(){
+        let output = run(FrameFilter::new(FilterMode::Blend, DEFAULT_OR_FRAMES), &[[1, 0], [0, 2], [0, 2]]);
+        assert_eq!(output[0], [[200, 100, 50, 255], [0, 0, 0, 255]]);
+        assert_eq!(output[1], [[100, 50, 25, 255], [0, 0, 100, 255]]);
+        assert_eq!(output[2], [[0, 0, 0, 255], [0, 0, 200, 255]]);
+    }
+
+    #[test]
+    fn or_combines_the_last_frames(){
+        let output = run(FrameFilter::new(FilterMode::Or, 2), &[[1, 0], [2, 0], [0, 0], [0, 0]]);
+        assert_eq!(output[0][0], COLORS[1]);
+        assert_eq!(output[1][0], COLORS[3]);
+        assert_eq!(output[2][0], COLORS[2]);
+        assert_eq!(output[3][0], COLORS[0]);
+        assert!(output.iter().all(|pixels| pixels[1] == COLORS[0]));
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use crate::backend::audio::Beeper;
+use crate::backend::backend::{Backend, Keys};
+use crate::chip8::display::{LORES_HEIGHT, LORES_WIDTH};
+
+const PLANE_CHARS: [char; 4] = ['.', '#', 'o', '@'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent{
+    pub frame: u64,
+    pub key: Keys,
+    pub pressed: bool,
+}
+
+pub struct HeadlessBackend{
+    pub framebuffer: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub frames_drawn: usize,
+    pub keys_pressed: HashMap<Keys, bool>,
+    pub key_waits: Vec<u64>,
+    pub beeping: bool,
+    pub beep_ticks: usize,
+    pub beeper: Option<Beeper>,
+
+    script: Vec<KeyEvent>,
+    frame: u64,
+    waiting_key: bool,
+    waited_key: Option<Keys>,
+}
+
+impl HeadlessBackend{
+    pub fn new() -> Self{
+        Self{
+            framebuffer: vec![0; LORES_WIDTH * LORES_HEIGHT],
+            width: LORES_WIDTH,
+            height: LORES_HEIGHT,
+            frames_drawn: 0,
+            keys_pressed: HashMap::new(),
+            key_waits: Vec::new(),
+            beeping: false,
+            beep_ticks: 0,
+            beeper: None,
+            script: Vec::new(),
+            frame: 0,
+            waiting_key: false,
+            waited_key: None,
+        }
+    }
+
+    pub fn with_script(mut script: Vec<KeyEvent>) -> Self{
+        script.sort_by_key(|event| event.frame);
+        Self{
+            script,
+            ..Self::new()
+        }
+    }
+
+    pub fn set_key(&mut self, key: Keys, pressed: bool){
+        let was_pressed = self.poll_key(key);
+        self.keys_pressed.insert(key, pressed);
+        if pressed && !was_pressed && self.waiting_key{
+            self.waiting_key = false;
+            self.waited_key = Some(key);
+        }
+    }
+
+    pub fn begin_frame(&mut self, frame: u64){
+        self.frame = frame;
+        let due = self.script.iter().take_while(|event| event.frame <= frame).count();
+        let events: Vec<KeyEvent> = self.script.drain(..due).collect();
+        for event in events{
+            self.set_key(event.key, event.pressed);
+        }
+    }
+
+    pub fn take_waited_key(&mut self) -> Option<Keys>{
+        self.waited_key.take()
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool{
+        self.framebuffer[x + y * self.width] != 0
+    }
+
+    pub fn screen_to_string(&self) -> String{
+        let mut screen = String::with_capacity((self.width + 1) * self.height);
+        for y in 0..self.height{
+            for x in 0..self.width{
+                screen.push(PLANE_CHARS[self.framebuffer[x + y * self.width] as usize & 0x3]);
+            }
+            screen.push('\n');
+        }
+        screen
+    }
+}
+
+impl Default for HeadlessBackend{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+impl Backend for HeadlessBackend{
+    fn draw_frame(&mut self, framebuffer: &[u8], width: usize, height: usize) {
+        self.framebuffer.clear();
+        self.framebuffer.extend_from_slice(framebuffer);
+        self.width = width;
+        self.height = height;
+        self.frames_drawn += 1;
+    }
+
+    fn poll_key(&mut self, key: Keys) -> bool {
+        self.keys_pressed.get(&key).copied().unwrap_or(false)
+    }
+
+    fn wait_for_key(&mut self) {
+        self.key_waits.push(self.frame);
+        self.waiting_key = true;
+    }
+
+    fn set_beep(&mut self, on: bool) {
+        self.beeping = on;
+        if on{
+            self.beep_ticks += 1;
+        }
+        if let Some(beeper) = &mut self.beeper{
+            beeper.render_tick(on);
+        }
+    }
+
+    fn set_audio_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        if let Some(beeper) = &mut self.beeper{
+            beeper.set_pattern(pattern, pitch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::chip8::cpu::{Cpu, StepOutcome};
+    use crate::chip8::scheduler::{Scheduler, DEFAULT_INSTRUCTIONS_PER_FRAME};
+
+    fn run_rom(path: &str, frames: u64) -> HeadlessBackend{
+        let mut cpu = Cpu::new();
+        cpu.load_rom_from_path(path).unwrap();
+        let mut backend = HeadlessBackend::new();
+        let mut scheduler = Scheduler::new(DEFAULT_INSTRUCTIONS_PER_FRAME);
+        for frame in 0..frames{
+            backend.begin_frame(frame);
+            let outcome = scheduler.run_frame(&mut cpu, &mut backend).unwrap();
+            cpu.present_if_dirty(&mut backend);
+            if outcome == StepOutcome::Exited{
+                break;
+            }
+        }
+        backend
+    }
+
+    fn screen(rows: &str) -> String{
+        rows.lines().map(|row| format!("{}\n", row.trim())).filter(|row| row != "\n").collect()
+    }
+
+    #[test]
+    fn ibm_logo(){
+        let backend = run_rom("programs/IBM_Logo.ch8", 60);
+        assert_eq!(backend.screen_to_string(), screen("
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ............########.#########...#####.........#####............
+            ................................................................
+            ............########.###########.######.......######............
+            ................................................................
+            ..............####.....###...###...#####.....#####..............
+            ................................................................
+            ..............####.....#######.....#######.#######..............
+            ................................................................
+            ..............####.....#######.....###.#######.###..............
+            ................................................................
+            ..............####.....###...###...###..#####..###..............
+            ................................................................
+            ............########.###########.#####...###...#####............
+            ................................................................
+            ............########.#########...#####....#....#####............
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+            ................................................................
+        "));
+    }
+
+    #[test]
+    fn test_opcode_passes(){
+        let backend = run_rom("programs/test_opcode.ch8", 60);
+        assert_eq!(backend.screen_to_string(), screen("
+            ................................................................
+            .###.#.#..###.#.#......###.###..###.#.#.....###..##.###.#.#.....
+            ..##..#...#.#.##.......#.#.##...#.#.##......###..#..#.#.##......
+            ...#.#.#..#.#.#.#......#.#.#....#.#.#.#.....#.#...#.#.#.#.#.....
+            .###.#.#..###.#.#......###.###..###.#.#.....###..#..###.#.#.....
+            ................................................................
+            .#.#.#.#..###.#.#......###.###..###.#.#.....###.###.###.#.#.....
+            .###..#...#.#.##.......###.#.#..#.#.##......###.#...#.#.##......
+            ...#.#.#..#.#.#.#......#.#.#.#..#.#.#.#.....#.#.###.#.#.#.#.....
+            ...#.#.#..###.#.#......###.###..###.#.#.....###.###.###.#.#.....
+            ................................................................
+            ..##.#.#..###.#.#......###.##...###.#.#.....###.###.###.#.#.....
+            ..#...#...#.#.##.......###..#...#.#.##......###.##..#.#.##......
+            ...#.#.#..#.#.#.#......#.#..#...#.#.#.#.....#.#.#...#.#.#.#.....
+            ..#..#.#..###.#.#......###.###..###.#.#.....###.###.###.#.#.....
+            ................................................................
+            .###.#.#..###.#.#......###.###..###.#.#.....###..##.###.#.#.....
+            ...#..#...#.#.##.......###...#..#.#.##......#....#..#.#.##......
+            ...#.#.#..#.#.#.#......#.#.##...#.#.#.#.....##....#.#.#.#.#.....
+            ...#.#.#..###.#.#......###.###..###.#.#.....#....#..###.#.#.....
+            ................................................................
+            .###.#.#..###.#.#......###.###..###.#.#.....###.###.###.#.#.....
+            .###..#...#.#.##.......###..##..#.#.##......#....##.#.#.##......
+            ...#.#.#..#.#.#.#......#.#...#..#.#.#.#.....##....#.#.#.#.#.....
+            .###.#.#..###.#.#......###.###..###.#.#.....#...###.###.#.#.....
+            ................................................................
+            ..#..#.#..###.#.#......###.#.#..###.#.#.....##..#.#.###.#.#.....
+            .#.#..#...#.#.##.......###.###..#.#.##.......#...#..#.#.##......
+            .###.#.#..#.#.#.#......#.#...#..#.#.#.#......#..#.#.#.#.#.#.....
+            .#.#.#.#..###.#.#......###...#..###.#.#.....###.#.#.###.#.#.....
+            ................................................................
+            ................................................................
+        "));
+    }
+
+    #[test]
+    fn scripted_key_ends_wait(){
+        let mut backend = HeadlessBackend::with_script(vec![
+            KeyEvent{frame: 2, key: Keys::KEYW, pressed: true},
+        ]);
+        backend.begin_frame(1);
+        backend.wait_for_key();
+        assert_eq!(backend.key_waits, vec![1]);
+        assert_eq!(backend.take_waited_key(), None);
+        backend.begin_frame(2);
+        assert_eq!(backend.take_waited_key(), Some(Keys::KEYW));
+        assert!(backend.poll_key(Keys::KEYW));
+    }
+}
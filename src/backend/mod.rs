@@ -1,2 +1,9 @@
+pub mod audio;
+#[allow(clippy::module_inception)]
 pub mod backend;
+pub mod filter;
+pub mod headless_backend;
+pub mod palette;
 pub mod pixels_backend;
+pub mod postfx;
+pub mod postfx_gpu;
@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::Path;
+
+pub type Rgba = [u8; 4];
+
+// Display colors indexed by plane bits: background, plane 1, plane 2 and both
+// planes, so XO-CHIP programs get all four. The border fills the window
+// around the scaled screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette{
+    pub name: String,
+    pub colors: [Rgba; 4],
+    pub border: Rgba,
+}
+
+impl Palette{
+    pub fn new(name: &str, colors: [Rgba; 4]) -> Self{
+        Self{name: name.to_string(), colors, border: colors[0]}
+    }
+
+    pub fn classic() -> Self{
+        Self::new("classic", [rgb(0x000000), rgb(0xFFFFFF), rgb(0xAAAAAA), rgb(0x555555)])
+    }
+
+    pub fn amber() -> Self{
+        Self::new("amber", [rgb(0x1A0F00), rgb(0xFFB000), rgb(0x995F00), rgb(0xFFD880)])
+    }
+
+    pub fn green() -> Self{
+        Self::new("green", [rgb(0x001400), rgb(0x33FF33), rgb(0x1A991A), rgb(0xA0FFA0)])
+    }
+
+    pub fn lcd() -> Self{
+        Self::new("lcd", [rgb(0x9BBC0F), rgb(0x0F380F), rgb(0x8BAC0F), rgb(0x306230)])
+    }
+
+    pub fn high_contrast() -> Self{
+        Self::new("high-contrast", [rgb(0x000000), rgb(0xFFFFFF), rgb(0xFFFF00), rgb(0x00FFFF)])
+    }
+
+    pub fn builtin() -> Vec<Palette>{
+        vec![Self::classic(), Self::amber(), Self::green(), Self::lcd(), Self::high_contrast()]
+    }
+
+    pub fn by_name<'a>(palettes: &'a [Palette], name: &str) -> Result<&'a Palette, String>{
+        palettes.iter().find(|palette| palette.name == name).ok_or_else(|| {
+            let names: Vec<&str> = palettes.iter().map(|palette| palette.name.as_str()).collect();
+            format!("unknown palette `{name}`, expected one of: {}", names.join(", "))
+        })
+    }
+
+    // One palette per line: a name followed by the background, plane 1, plane 2
+    // and both-planes colors and optionally a border color, all as hex RGB,
+    // e.g. `sunset #1A1C2C #F4F4F4 #EF7D57 #FFCD75`.
+    pub fn parse(text: &str) -> Result<Vec<Palette>, String>{
+        let mut palettes = Vec::new();
+        for (idx, line) in text.lines().enumerate(){
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#'){
+                continue;
+            }
+            let error = |message: String| format!("line {}: {message}", idx + 1);
+            let mut words = line.split_whitespace();
+            let name = words.next().unwrap();
+            let colors = words.map(parse_color).collect::<Result<Vec<Rgba>, String>>().map_err(error)?;
+            let mut palette = match colors[..]{
+                [background, plane1, plane2, both, ..] => Palette::new(name, [background, plane1, plane2, both]),
+                _ => return Err(error(format!("`{name}` needs four colors"))),
+            };
+            match colors[4..]{
+                [] => (),
+                [border] => palette.border = border,
+                _ => return Err(error(format!("`{name}` has more than five colors"))),
+            }
+            palettes.push(palette);
+        }
+        Ok(palettes)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Palette>, String>{
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("could not read `{}`: {err}", path.display()))?;
+        Self::parse(&text).map_err(|err| format!("`{}` {err}", path.display()))
+    }
+}
+
+impl Default for Palette{
+    fn default() -> Self{
+        Self::classic()
+    }
+}
+
+fn rgb(color: u32) -> Rgba{
+    let [_, r, g, b] = color.to_be_bytes();
+    [r, g, b, 255]
+}
+
+fn parse_color(value: &str) -> Result<Rgba, String>{
+    let digits = value.trim_start_matches('#');
+    match u32::from_str_radix(digits, 16){
+        Ok(color) if digits.len() == 6 => Ok(rgb(color)),
+        _ => Err(format!("`{value}` is not a hex color like #FFB000")),
+    }
+}
+
+// The palettes the frontend cycles through and the one in use.
+#[derive(Debug, Clone)]
+pub struct PaletteSet{
+    palettes: Vec<Palette>,
+    current: usize,
+}
+
+impl PaletteSet{
+    // Palettes from a config file replace built-in ones with the same name.
+    pub fn new(extra: Vec<Palette>, current: &str) -> Result<Self, String>{
+        let mut palettes = Palette::builtin();
+        for palette in extra{
+            match palettes.iter_mut().find(|existing| existing.name == palette.name){
+                Some(existing) => *existing = palette,
+                None => palettes.push(palette),
+            }
+        }
+        let current = Palette::by_name(&palettes, current)?.name.clone();
+        let current = palettes.iter().position(|palette| palette.name == current).unwrap();
+        Ok(Self{palettes, current})
+    }
+
+    pub fn current(&self) -> &Palette{
+        &self.palettes[self.current]
+    }
+
+    pub fn cycle(&mut self) -> &Palette{
+        self.current = (self.current + 1) % self.palettes.len();
+        self.current()
+    }
+}
+
+impl Default for PaletteSet{
+    fn default() -> Self{
+        Self{palettes: Palette::builtin(), current: 0}
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn parses_palettes(){
+        let text = "# name, background, plane 1, plane 2, both planes and border\n\
+                    sunset #1A1C2C #F4F4F4 #EF7D57 #FFCD75\n\
+                    \n\
+                    framed 000000 FFFFFF AAAAAA 555555 #203040\n";
+        let palettes = Palette::parse(text).unwrap();
+        assert_eq!(palettes.len(), 2);
+        assert_eq!(palettes[0].name, "sunset");
+        assert_eq!(palettes[0].colors, [
+            [0x1A, 0x1C, 0x2C, 255],
+            [0xF4, 0xF4, 0xF4, 255],
+            [0xEF, 0x7D, 0x57, 255],
+            [0xFF, 0xCD, 0x75, 255],
+        ]);
+        assert_eq!(palettes[0].border, palettes[0].colors[0]);
+        assert_eq!(palettes[1].colors, Palette::classic().colors);
+        assert_eq!(palettes[1].border, [0x20, 0x30, 0x40, 255]);
+    }
+
+    #[test]
+    fn rejects_bad_colors(){
+        let err = Palette::parse("sunset #1A1C2C #F4F4F4 #EF7D5 #FFCD75").unwrap_err();
+        assert_eq!(err, "line 1: `#EF7D5` is not a hex color like #FFB000");
+        let err = Palette::parse("\nsunset #1A1C2C #F4F4F4 #EF7D57 #GGCD75").unwrap_err();
+        assert_eq!(err, "line 2: `#GGCD75` is not a hex color like #FFB000");
+    }
+
+    #[test]
+    fn rejects_wrong_color_counts(){
+        // Two colors would do for plain CHIP-8, but every palette covers both planes.
+        let err = Palette::parse("mono #000000 #FFFFFF").unwrap_err();
+        assert_eq!(err, "line 1: `mono` needs four colors");
+        let err = Palette::parse("mono").unwrap_err();
+        assert_eq!(err, "line 1: `mono` needs four colors");
+        let err = Palette::parse("wide #000000 #FFFFFF #AAAAAA #555555 #203040 #102030").unwrap_err();
+        assert_eq!(err, "line 1: `wide` has more than five colors");
+    }
+
+    #[test]
+    fn file_palettes_replace_builtins(){
+        let amber = Palette::new("amber", Palette::green().colors);
+        let mut set = PaletteSet::new(vec![amber.clone()], "amber").unwrap();
+        assert_eq!(set.current(), &amber);
+        assert_eq!(set.cycle().name, "green");
+        let err = PaletteSet::new(Vec::new(), "sepia").unwrap_err();
+        assert_eq!(err, "unknown palette `sepia`, expected one of: classic, amber, green, lcd, high-contrast");
+    }
+}
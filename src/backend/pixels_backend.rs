@@ -1,26 +1,86 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use pixels::{Pixels, SurfaceTexture};
 use winit::application::ApplicationHandler;
+use winit::dpi::{LogicalSize, PhysicalSize};
 use winit::event::{ElementState, WindowEvent};
-use winit::event_loop::EventLoop;
+use winit::event_loop::{ControlFlow, EventLoop};
 use winit::keyboard::{KeyCode, PhysicalKey};
-use winit::window::{Window, WindowAttributes};
+use winit::window::{Fullscreen, Window, WindowAttributes};
 
+use crate::backend::audio::Beeper;
 use crate::backend::backend::{Backend, Keys};
-use crate::chip8::cpu::Cpu;
+use crate::backend::filter::FrameFilter;
+use crate::backend::palette::{PaletteSet, Rgba};
+use crate::backend::postfx::{Effects, SoftwarePostFx};
+use crate::backend::postfx_gpu::GpuPostFx;
+use crate::chip8::cpu::{Cpu, StepOutcome};
+use crate::chip8::display::{HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH};
+use crate::chip8::error::CpuFault;
+use crate::chip8::rewind::RewindBuffer;
+use crate::chip8::scheduler::{Scheduler, Speed, FRAMES_PER_SECOND};
+use crate::chip8::snapshot::SaveSlots;
 
 
+// Longest the emulator runs before handing control back to the event loop,
+// which bounds uncapped speed and catching up to one host frame.
+const FRAME_BUDGET: Duration = Duration::from_micros(16_667);
 
+// How the screen is turned into pixels on the host display. Pixels scales the
+// buffer by the largest whole factor that fits the window and letterboxes the
+// rest with the palette's border color, so the 2:1 aspect ratio holds at any
+// window size and in both resolutions.
+pub struct Appearance{
+    pub scale: u32,
+    pub fullscreen: bool,
+    pub filter: FrameFilter,
+    pub palettes: PaletteSet,
+    pub effects: Effects,
+    pub software_postfx: bool,
+}
+
+pub enum PostFx{
+    Gpu(GpuPostFx),
+    // The effects are rendered into `staging` sized output that replaces the
+    // pixels buffer, which the default renderer then scales as usual.
+    Software{
+        postfx: SoftwarePostFx,
+        staging: Vec<u8>,
+    },
+}
+
+#[allow(clippy::large_enum_variant)]
 pub enum PixelsBackend{
-    Uninitialized,
+    Uninitialized{
+        cpu: Box<Cpu>,
+        scheduler: Scheduler,
+        beeper: Option<Beeper>,
+        save_slots: Option<SaveSlots>,
+        rewind: Option<RewindBuffer>,
+        appearance: Option<Appearance>,
+    },
     Initialized{
         inner: PixelsInner,
-        cpu: Cpu
+        cpu: Box<Cpu>,
+        scheduler: Scheduler,
     },
 }
 
+impl PixelsBackend{
+    pub fn new(cpu: Cpu, scheduler: Scheduler, beeper: Option<Beeper>, save_slots: SaveSlots, rewind: RewindBuffer, appearance: Appearance) -> Self{
+        PixelsBackend::Uninitialized{
+            cpu: Box::new(cpu),
+            scheduler,
+            beeper,
+            save_slots: Some(save_slots),
+            rewind: Some(rewind),
+            appearance: Some(appearance),
+        }
+    }
+}
+
 pub enum WaitingKey{
     No,
     Yes,
@@ -29,24 +89,202 @@ pub enum WaitingKey{
 pub struct PixelsInner{
     pub pixels: Pixels<'static>,
     pub window: Arc<Window>,
+    pub buffer_size: (usize, usize),
     pub waiting_key: WaitingKey,
     pub keys_pressed: HashMap<Keys, bool>,
+    pub fault: Option<CpuFault>,
+    pub beeper: Option<Beeper>,
+    pub save_slots: SaveSlots,
+    pub rewind: RewindBuffer,
+    pub rewinding: bool,
+    pub filter: FrameFilter,
+    pub palettes: PaletteSet,
+    pub postfx: PostFx,
+    pub effects: Effects,
+    // What F10 switches to: the configured effects while they are off.
+    pub toggled_effects: Effects,
+    pub status: Option<String>,
+    pub rate: String,
+    // Instant and frame count of the last uncapped speed measurement.
+    pub rate_sample: (Instant, u64),
 }
 
 impl PixelsInner{
-    pub fn new(window: Arc<Window>) -> Self{
-        let surface_texture = SurfaceTexture::new(64, 32, window.clone());
-        let mut pixels = Pixels::new(64, 32, surface_texture).unwrap();
-        pixels.clear_color(pixels::wgpu::Color{r: 0.0, g: 0.0, b: 0.3, a: 1.0});
+    pub fn new(window: Arc<Window>, beeper: Option<Beeper>, save_slots: SaveSlots, rewind: RewindBuffer, appearance: Appearance) -> Self{
+        let size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(size.width, size.height, window.clone());
+        let mut pixels = Pixels::new(LORES_WIDTH as u32, LORES_HEIGHT as u32, surface_texture).unwrap();
+        pixels.clear_color(wgpu_color(appearance.palettes.current().border));
+        let postfx = if appearance.software_postfx{
+            PostFx::Software{postfx: SoftwarePostFx::new(), staging: Vec::new()}
+        } else {
+            PostFx::Gpu(GpuPostFx::new(&pixels))
+        };
+        let toggled_effects = if appearance.effects.is_empty() { Effects::CRT } else { Effects::NONE };
 
         Self{
             window: window.clone(),
+            buffer_size: (LORES_WIDTH, LORES_HEIGHT),
             pixels,
             waiting_key: WaitingKey::No,
             keys_pressed: HashMap::new(),
+            fault: None,
+            beeper,
+            save_slots,
+            rewind,
+            rewinding: false,
+            filter: appearance.filter,
+            palettes: appearance.palettes,
+            postfx,
+            effects: appearance.effects,
+            toggled_effects,
+            status: None,
+            rate: Speed::NORMAL.to_string(),
+            rate_sample: (Instant::now(), 0),
+        }
+    }
+
+    pub fn handle_hotkey(&mut self, code: KeyCode, cpu: &mut Cpu, scheduler: &mut Scheduler){
+        match code{
+            KeyCode::F2 => {
+                self.save_slots.previous_slot();
+                self.show_status(&format!("slot {}", self.save_slots.slot()));
+            },
+            KeyCode::F3 => {
+                self.save_slots.next_slot();
+                self.show_status(&format!("slot {}", self.save_slots.slot()));
+            },
+            KeyCode::F5 => match self.save_slots.save(&cpu.snapshot()){
+                Ok(_) => self.show_status(&format!("saved slot {}", self.save_slots.slot())),
+                Err(err) => self.show_status(&format!("could not save slot {}: {err}", self.save_slots.slot())),
+            },
+            KeyCode::F9 => match self.save_slots.load(){
+                Ok(snapshot) => {
+                    cpu.restore(&snapshot);
+                    self.resync(cpu);
+                    self.rewind.clear();
+                    self.show_status(&format!("loaded slot {}", self.save_slots.slot()));
+                },
+                Err(err) => self.show_status(&format!("could not load slot {}: {err}", self.save_slots.slot())),
+            },
+            KeyCode::F7 => {
+                self.filter.set_mode(self.filter.mode().next());
+                self.show_status(&format!("filter {}", self.filter.mode()));
+                cpu.present(self);
+            },
+            KeyCode::F8 => {
+                let palette = self.palettes.cycle();
+                self.pixels.clear_color(wgpu_color(palette.border));
+                let status = format!("palette {}", palette.name);
+                self.show_status(&status);
+                cpu.present(self);
+            },
+            KeyCode::F10 => {
+                std::mem::swap(&mut self.effects, &mut self.toggled_effects);
+                self.show_status(&format!("effects {}", self.effects));
+                cpu.present(self);
+            },
+            KeyCode::F11 => self.toggle_fullscreen(),
+            KeyCode::KeyP => scheduler.set_paused(!scheduler.is_paused()),
+            KeyCode::KeyN => scheduler.advance_frame(),
+            KeyCode::Minus => scheduler.set_speed(scheduler.speed().slower()),
+            KeyCode::Equal => scheduler.set_speed(scheduler.speed().faster()),
+            KeyCode::Digit0 => scheduler.set_speed(Speed::NORMAL),
+            _ => (),
+        }
+        self.update_rate(scheduler);
+    }
+
+    // Keeps the rate shown in the title and the beeper's sample rate in line
+    // with the scheduler. Uncapped speed is measured, and only refreshed once a
+    // second to stay readable.
+    pub fn update_rate(&mut self, scheduler: &Scheduler){
+        let rate = match scheduler.speed(){
+            _ if scheduler.is_paused() => "paused".to_string(),
+            Speed::Scaled(multiplier) => {
+                self.set_audio_speed(multiplier);
+                scheduler.speed().to_string()
+            },
+            Speed::Uncapped if !self.rate.starts_with("uncapped") => {
+                self.rate_sample = (Instant::now(), scheduler.frames_run());
+                "uncapped".to_string()
+            },
+            Speed::Uncapped => {
+                let (since, frames) = self.rate_sample;
+                let elapsed = since.elapsed();
+                if elapsed < Duration::from_secs(1){
+                    return;
+                }
+                self.rate_sample = (Instant::now(), scheduler.frames_run());
+                let multiplier = (scheduler.frames_run() - frames) as f64 / elapsed.as_secs_f64() / FRAMES_PER_SECOND as f64;
+                self.set_audio_speed(multiplier);
+                format!("uncapped, {multiplier:.1}x")
+            },
+        };
+        if rate != self.rate{
+            self.rate = rate;
+            self.refresh_title();
+        }
+    }
+
+    fn set_audio_speed(&mut self, multiplier: f64){
+        if let Some(beeper) = &mut self.beeper{
+            beeper.set_speed(multiplier);
+        }
+    }
+
+    pub fn set_rewinding(&mut self, rewinding: bool){
+        if self.rewinding == rewinding{
+            return;
+        }
+        self.rewinding = rewinding;
+        if rewinding{
+            self.show_status("rewinding");
+        } else {
+            self.status = None;
+            self.refresh_title();
+        }
+    }
+
+    // Steps one frame back, leaving the Cpu alone once the buffer runs dry.
+    pub fn rewind_frame(&mut self, cpu: &mut Cpu){
+        if let Some(snapshot) = self.rewind.pop(){
+            cpu.restore(&snapshot);
+            self.resync(cpu);
+        }
+    }
+
+    // Brings the frontend in line with a Cpu whose state was replaced wholesale.
+    pub fn resync(&mut self, cpu: &mut Cpu){
+        self.fault = None;
+        self.waiting_key = if cpu.is_waiting_for_key() { WaitingKey::Yes } else { WaitingKey::No };
+        cpu.sync_backend(self);
+    }
+
+    pub fn toggle_fullscreen(&self){
+        match self.window.fullscreen(){
+            Some(_) => self.window.set_fullscreen(None),
+            None => self.window.set_fullscreen(Some(Fullscreen::Borderless(None))),
+        }
+    }
+
+    pub fn show_status(&mut self, status: &str){
+        self.status = Some(status.to_string());
+        self.refresh_title();
+    }
+
+    pub fn refresh_title(&self){
+        match &self.status{
+            Some(status) => self.window.set_title(&format!("pico8 [{}] - {status}", self.rate)),
+            None => self.window.set_title(&format!("pico8 [{}]", self.rate)),
         }
     }
 
+    pub fn halt(&mut self, fault: CpuFault){
+        self.show_status(&format!("halted: {fault}"));
+        self.fault = Some(fault);
+    }
+
     pub fn keycode_to_keys(&self, code: KeyCode) -> Option<Keys>{
         match code{
             KeyCode::Digit1 => Some(Keys::KEY1),
@@ -71,12 +309,29 @@ impl PixelsInner{
 }
 
 impl Backend for PixelsInner{
-    fn draw_frame(&mut self, framebuffer: &[u8; 64 * 32]) {
-        let frame = self.pixels.frame_mut();
-        for (i, &pixel) in framebuffer.iter().enumerate(){
-            let rgba_idx = i * 4;
-            let color = if pixel > 0 { 255 } else { 0 };
-            frame[rgba_idx..rgba_idx + 4].copy_from_slice(&[color, color, color, 255]);
+    fn draw_frame(&mut self, framebuffer: &[u8], width: usize, height: usize) {
+        let colors = self.palettes.current().colors;
+        let software = match &mut self.postfx{
+            PostFx::Software{postfx, staging} if !self.effects.is_empty() => Some((postfx, staging)),
+            _ => None,
+        };
+        let buffer_size = match software{
+            Some(_) => SoftwarePostFx::output_size(width, height),
+            None => (width, height),
+        };
+        if self.buffer_size != buffer_size{
+            self.pixels.resize_buffer(buffer_size.0 as u32, buffer_size.1 as u32).unwrap();
+            self.buffer_size = buffer_size;
+        }
+
+        match software{
+            Some((postfx, staging)) => {
+                staging.resize(framebuffer.len() * 4, 0);
+                self.filter.apply(framebuffer, &colors, staging);
+                let output = postfx.render(staging, width, height, self.effects);
+                self.pixels.frame_mut().copy_from_slice(output);
+            },
+            None => self.filter.apply(framebuffer, &colors, self.pixels.frame_mut()),
         }
         self.window.request_redraw();
     }
@@ -92,19 +347,46 @@ impl Backend for PixelsInner{
     fn wait_for_key(&mut self) { 
         self.waiting_key = WaitingKey::Yes;
     }
+
+    fn set_beep(&mut self, on: bool) {
+        if let Some(beeper) = &mut self.beeper{
+            beeper.render_tick(on);
+        }
+    }
+
+    fn set_audio_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        if let Some(beeper) = &mut self.beeper{
+            beeper.set_pattern(pattern, pitch);
+        }
+    }
 }
 
 impl ApplicationHandler for PixelsBackend{
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        match self{
-            PixelsBackend::Uninitialized => {
-                let window = event_loop.create_window(WindowAttributes::default()).unwrap();
-                let window = Arc::new(window);
-                let inner = PixelsInner::new(window);
-                let cpu = Cpu::new();
-                *self = PixelsBackend::Initialized{inner, cpu};
-            },
-            PixelsBackend::Initialized{..} => return
+        if let PixelsBackend::Uninitialized{cpu, scheduler, beeper, save_slots, rewind, appearance} = self{
+            let appearance = appearance.take().unwrap();
+            let fullscreen = appearance.fullscreen.then_some(Fullscreen::Borderless(None));
+            // The surface can't be smaller than the hi-res buffer, or a SCHIP
+            // program switching to 128x64 would have nowhere to go.
+            let attributes = WindowAttributes::default()
+                .with_title("pico8")
+                .with_inner_size(LogicalSize::new(LORES_WIDTH as u32 * appearance.scale, LORES_HEIGHT as u32 * appearance.scale))
+                .with_min_inner_size(PhysicalSize::new(HIRES_WIDTH as u32, HIRES_HEIGHT as u32))
+                .with_fullscreen(fullscreen);
+            let window = event_loop.create_window(attributes).unwrap();
+            let window = Arc::new(window);
+            let mut inner = PixelsInner::new(
+                window,
+                beeper.take(),
+                save_slots.take().unwrap(),
+                rewind.take().unwrap(),
+                appearance,
+            );
+            let cpu = std::mem::take(cpu);
+            let scheduler = std::mem::take(scheduler);
+            inner.update_rate(&scheduler);
+            inner.refresh_title();
+            *self = PixelsBackend::Initialized{inner, cpu, scheduler};
         }
     }
 
@@ -115,12 +397,11 @@ impl ApplicationHandler for PixelsBackend{
         event: winit::event::WindowEvent,
     ) {
         match event{
+            // Minimizing reports a zero size, which no surface can have.
+            WindowEvent::Resized(size) if size.width == 0 || size.height == 0 => (),
             WindowEvent::Resized(size) => {
-                match self {
-                    PixelsBackend::Initialized{inner, cpu} => {
-                        inner.pixels.resize_surface(size.width, size.height).unwrap();
-                    },
-                    PixelsBackend::Uninitialized => (),
+                if let PixelsBackend::Initialized{inner, ..} = self {
+                    inner.pixels.resize_surface(size.width, size.height).unwrap();
                 }
             },
 
@@ -128,12 +409,30 @@ impl ApplicationHandler for PixelsBackend{
                 event_loop.exit();
             }
 
+            WindowEvent::KeyboardInput{event, ..} => {
+                if let PixelsBackend::Initialized{inner, cpu, scheduler} = self {
+                    match (event.physical_key, event.state, event.repeat){
+                        (PhysicalKey::Code(KeyCode::Backspace), state, _) => inner.set_rewinding(state.is_pressed()),
+                        (PhysicalKey::Code(code), ElementState::Pressed, false) => inner.handle_hotkey(code, cpu, scheduler),
+                        _ => (),
+                    }
+                }
+            }
+
             WindowEvent::RedrawRequested => {
-                match self {
-                    PixelsBackend::Initialized{inner, cpu} => {
-                        inner.pixels.render();
-                    },
-                    _ => (),
+                if let PixelsBackend::Initialized{inner, ..} = self {
+                    match &inner.postfx{
+                        PostFx::Gpu(postfx) if !inner.effects.is_empty() => {
+                            let border = wgpu_color(inner.palettes.current().border);
+                            inner.pixels.render_with(|encoder, target, context| {
+                                postfx.render(encoder, target, context, inner.effects, border);
+                                Ok(())
+                            });
+                        },
+                        _ => {
+                            inner.pixels.render();
+                        },
+                    }
                 }
             }
 
@@ -144,12 +443,45 @@ impl ApplicationHandler for PixelsBackend{
 
     fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         match self {
-            PixelsBackend::Uninitialized => (),
-            PixelsBackend::Initialized{ref mut inner, ref mut cpu} => {
-                cpu.update_timers();
-                match inner.waiting_key{
-                    WaitingKey::No => cpu.tick(inner),
-                    WaitingKey::Yes => return,
+            PixelsBackend::Uninitialized{..} => (),
+            PixelsBackend::Initialized{ref mut inner, ref mut cpu, ref mut scheduler} => {
+                let now = Instant::now();
+                let frames = scheduler.frames_due(now);
+                let frames_before = scheduler.frames_run();
+                for _ in 0..frames{
+                    if Instant::now() >= now + FRAME_BUDGET{
+                        break;
+                    }
+                    if inner.rewinding{
+                        inner.rewind_frame(cpu);
+                        continue;
+                    }
+                    if inner.fault.is_some(){
+                        break;
+                    }
+                    inner.rewind.push(&cpu.snapshot());
+                    match scheduler.run_frame(cpu, inner){
+                        Ok(StepOutcome::Exited) => {
+                            event_loop.exit();
+                            return;
+                        },
+                        // Nothing runs until the key comes, so there's no point
+                        // in more frames, or more rewind snapshots, right now.
+                        Ok(StepOutcome::WaitingForKey) => break,
+                        Ok(_) => (),
+                        Err(fault) => inner.halt(fault),
+                    }
+                }
+                // The filters animate even when the screen doesn't change.
+                if inner.filter.is_active() && scheduler.frames_run() != frames_before{
+                    cpu.present(inner);
+                } else {
+                    cpu.present_if_dirty(inner);
+                }
+                inner.update_rate(scheduler);
+                match scheduler.next_frame(cpu, inner.fault.is_some()){
+                    Some(instant) => event_loop.set_control_flow(ControlFlow::WaitUntil(instant)),
+                    None => event_loop.set_control_flow(ControlFlow::Wait),
                 }
             }
         }
@@ -162,38 +494,35 @@ impl ApplicationHandler for PixelsBackend{
         event: winit::event::DeviceEvent,
     ) {
         match self{
-            Self::Uninitialized => return,
-            Self::Initialized { ref mut inner, ref mut cpu } => {
-                match event{
-                    winit::event::DeviceEvent::Key(raw) => {
-                        let state = raw.state;
-                        let physical_key = raw.physical_key;
-                        match physical_key {
-                            PhysicalKey::Code(code) => {
-                                let key = inner.keycode_to_keys(code);
-                                let Some(key) = key else {return};
-                                match state{
-                                    ElementState::Released => {inner.keys_pressed.insert(key, false);},
-                                    ElementState::Pressed => {
-                                        inner.keys_pressed.insert(key, true);
-                                        match inner.waiting_key{
-                                            WaitingKey::Yes => {
-                                                cpu.waiting_key_pressed(key);
-                                                inner.waiting_key = WaitingKey::No;
-                                            },
-                                            WaitingKey::No => ()
-                                        }
+            Self::Uninitialized{..} => (),
+            Self::Initialized { ref mut inner, ref mut cpu, .. } => {
+                if let winit::event::DeviceEvent::Key(raw) = event{
+                    let state = raw.state;
+                    let physical_key = raw.physical_key;
+                    if let PhysicalKey::Code(code) = physical_key {
+                        let key = inner.keycode_to_keys(code);
+                        let Some(key) = key else {return};
+                        match state{
+                            ElementState::Released => {inner.keys_pressed.insert(key, false);},
+                            ElementState::Pressed => {
+                                inner.keys_pressed.insert(key, true);
+                                match inner.waiting_key{
+                                    WaitingKey::Yes => {
+                                        cpu.waiting_key_pressed(key);
+                                        inner.waiting_key = WaitingKey::No;
                                     },
-                                };
-                            }
-                            _ => ()
-                        }
+                                    WaitingKey::No => ()
+                                }
+                            },
+                        };
                     }
-                    _ => ()
                 }
-
-
             }
         }
     }
 }
+
+fn wgpu_color(color: Rgba) -> pixels::wgpu::Color{
+    let [r, g, b, a] = color.map(|channel| channel as f64 / 255.0);
+    pixels::wgpu::Color{r, g, b, a}
+}
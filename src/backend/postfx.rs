@@ -0,0 +1,287 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Output pixels per screen pixel in each direction in the software renderer,
+// and the cell size the shader's scanlines and grid are laid out on.
+pub const SUBPIXELS: usize = 4;
+// Brightness kept by the darkened rows and columns.
+const SCANLINE_SHADE: f32 = 0.55;
+const GRID_SHADE: f32 = 0.6;
+// How much of the 3x3 neighborhood average is added back as glow.
+const BLOOM_STRENGTH: f32 = 0.35;
+// Barrel distortion, the corners are pulled in by about this fraction.
+const CURVATURE: f32 = 0.08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect{
+    Scanlines,
+    Grid,
+    Bloom,
+    Curvature,
+}
+
+impl Effect{
+    pub const ALL: [Effect; 4] = [Effect::Scanlines, Effect::Grid, Effect::Bloom, Effect::Curvature];
+
+    pub fn name(&self) -> &'static str{
+        match self{
+            Effect::Scanlines => "scanlines",
+            Effect::Grid => "grid",
+            Effect::Bloom => "bloom",
+            Effect::Curvature => "curvature",
+        }
+    }
+}
+
+impl FromStr for Effect{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        Effect::ALL
+            .into_iter()
+            .find(|effect| effect.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Effect::ALL.iter().map(|effect| effect.name()).collect();
+                format!("unknown effect `{s}`, expected one of: {}", names.join(", "))
+            })
+    }
+}
+
+// The post-processing passes that are switched on, applied in the order of
+// `Effect::ALL`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Effects{
+    pub scanlines: bool,
+    pub grid: bool,
+    pub bloom: bool,
+    pub curvature: bool,
+}
+
+impl Effects{
+    pub const NONE: Effects = Effects{scanlines: false, grid: false, bloom: false, curvature: false};
+    pub const CRT: Effects = Effects{scanlines: true, grid: false, bloom: true, curvature: true};
+
+    pub fn contains(&self, effect: Effect) -> bool{
+        match effect{
+            Effect::Scanlines => self.scanlines,
+            Effect::Grid => self.grid,
+            Effect::Bloom => self.bloom,
+            Effect::Curvature => self.curvature,
+        }
+    }
+
+    pub fn insert(&mut self, effect: Effect){
+        match effect{
+            Effect::Scanlines => self.scanlines = true,
+            Effect::Grid => self.grid = true,
+            Effect::Bloom => self.bloom = true,
+            Effect::Curvature => self.curvature = true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool{
+        *self == Effects::NONE
+    }
+}
+
+impl fmt::Display for Effects{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        if self.is_empty(){
+            return write!(f, "none");
+        }
+        let names: Vec<&str> = Effect::ALL.iter().filter(|effect| self.contains(**effect)).map(|effect| effect.name()).collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+// A comma separated list of effect names, `crt` for scanlines, bloom and
+// curvature, or `none`.
+impl FromStr for Effects{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        let mut effects = Effects::NONE;
+        for name in s.split(','){
+            match name{
+                "none" => (),
+                "crt" => effects = Effects{grid: effects.grid, ..Effects::CRT},
+                name => effects.insert(name.parse()?),
+            }
+        }
+        Ok(effects)
+    }
+}
+
+// Applies the effects on the CPU into an offscreen RGBA buffer `SUBPIXELS`
+// times the size of the screen. Used where there is no GPU to run the shader,
+// and for screenshots.
+#[derive(Debug, Default)]
+pub struct SoftwarePostFx{
+    output: Vec<u8>,
+    glow: Vec<[f32; 3]>,
+}
+
+impl SoftwarePostFx{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    pub fn output_size(width: usize, height: usize) -> (usize, usize){
+        (width * SUBPIXELS, height * SUBPIXELS)
+    }
+
+    pub fn render(&mut self, rgba: &[u8], width: usize, height: usize, effects: Effects) -> &[u8]{
+        let (out_width, out_height) = Self::output_size(width, height);
+        self.output.resize(out_width * out_height * 4, 0);
+        if effects.bloom{
+            self.gather_glow(rgba, width, height);
+        }
+
+        for out_y in 0..out_height{
+            for out_x in 0..out_width{
+                let pixel = self.shade(rgba, width, height, out_x, out_y, effects);
+                let idx = (out_y * out_width + out_x) * 4;
+                self.output[idx..idx + 4].copy_from_slice(&pixel);
+            }
+        }
+        &self.output
+    }
+
+    fn shade(&self, rgba: &[u8], width: usize, height: usize, out_x: usize, out_y: usize, effects: Effects) -> [u8; 4]{
+        let (out_width, out_height) = Self::output_size(width, height);
+        // Sample at the center of the output pixel, in 0..1 across the screen.
+        let mut u = (out_x as f32 + 0.5) / out_width as f32;
+        let mut v = (out_y as f32 + 0.5) / out_height as f32;
+        if effects.curvature{
+            (u, v) = warp(u, v);
+            if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v){
+                return [0, 0, 0, 255];
+            }
+        }
+
+        let x = ((u * width as f32) as usize).min(width - 1);
+        let y = ((v * height as f32) as usize).min(height - 1);
+        let idx = (y * width + x) * 4;
+        let mut color = [rgba[idx] as f32, rgba[idx + 1] as f32, rgba[idx + 2] as f32];
+
+        let sub_x = (u * out_width as f32) as usize % SUBPIXELS;
+        let sub_y = (v * out_height as f32) as usize % SUBPIXELS;
+        let mut shade = 1.0;
+        if effects.grid && (sub_x == SUBPIXELS - 1 || sub_y == SUBPIXELS - 1){
+            shade *= GRID_SHADE;
+        }
+        if effects.scanlines && sub_y % 2 == 1{
+            shade *= SCANLINE_SHADE;
+        }
+        for (channel, value) in color.iter_mut().enumerate(){
+            *value *= shade;
+            if effects.bloom{
+                *value += self.glow[y * width + x][channel] * BLOOM_STRENGTH;
+            }
+        }
+        let [r, g, b] = color.map(|value| value.round().min(255.0) as u8);
+        [r, g, b, 255]
+    }
+
+    // The average color of each pixel's 3x3 neighborhood, repeating the edge
+    // pixels like the shader's clamped sampler does.
+    fn gather_glow(&mut self, rgba: &[u8], width: usize, height: usize){
+        self.glow.clear();
+        for y in 0..height{
+            for x in 0..width{
+                let mut sum = [0.0; 3];
+                for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))){
+                    let nx = x.saturating_add_signed(dx).min(width - 1);
+                    let ny = y.saturating_add_signed(dy).min(height - 1);
+                    let idx = (ny * width + nx) * 4;
+                    for (channel, total) in sum.iter_mut().enumerate(){
+                        *total += rgba[idx + channel] as f32;
+                    }
+                }
+                self.glow.push(sum.map(|total| total / 9.0));
+            }
+        }
+    }
+}
+
+// Barrel distortion of a 0..1 coordinate, the same formula as the shader's.
+fn warp(u: f32, v: f32) -> (f32, f32){
+    let (x, y) = (u * 2.0 - 1.0, v * 2.0 - 1.0);
+    let stretch = 1.0 + CURVATURE * (x * x + y * y);
+    ((x * stretch + 1.0) / 2.0, (y * stretch + 1.0) / 2.0)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    // A 2x2 screen: white, red / green, blue.
+    const SCREEN: [u8; 16] = [255, 255, 255, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
+
+    fn pixel(output: &[u8], width: usize, x: usize, y: usize) -> [u8; 4]{
+        let idx = (y * width * SUBPIXELS + x) * 4;
+        output[idx..idx + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn output_size(){
+        assert_eq!(SoftwarePostFx::output_size(64, 32), (256, 128));
+        assert_eq!(SoftwarePostFx::output_size(128, 64), (512, 256));
+    }
+
+    #[test]
+    fn no_effects_scales_up(){
+        let mut postfx = SoftwarePostFx::new();
+        let output = postfx.render(&SCREEN, 2, 2, Effects::NONE).to_vec();
+        assert_eq!(output.len(), 8 * 8 * 4);
+        assert_eq!(pixel(&output, 2, 0, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(&output, 2, 7, 3), [255, 0, 0, 255]);
+        assert_eq!(pixel(&output, 2, 3, 4), [0, 255, 0, 255]);
+        assert_eq!(pixel(&output, 2, 4, 7), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn scanlines_darken_odd_rows(){
+        let effects = Effects{scanlines: true, ..Effects::NONE};
+        let output = SoftwarePostFx::new().render(&SCREEN, 2, 2, effects).to_vec();
+        assert_eq!(pixel(&output, 2, 0, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(&output, 2, 0, 1), [140, 140, 140, 255]);
+        assert_eq!(pixel(&output, 2, 0, 2), [255, 255, 255, 255]);
+        assert_eq!(pixel(&output, 2, 4, 3), [140, 0, 0, 255]);
+    }
+
+    #[test]
+    fn grid_darkens_cell_edges(){
+        let effects = Effects{grid: true, ..Effects::NONE};
+        let output = SoftwarePostFx::new().render(&SCREEN, 2, 2, effects).to_vec();
+        assert_eq!(pixel(&output, 2, 0, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(&output, 2, 3, 0), [153, 153, 153, 255]);
+        assert_eq!(pixel(&output, 2, 0, 3), [153, 153, 153, 255]);
+    }
+
+    #[test]
+    fn bloom_adds_neighbor_glow(){
+        let gray = [100, 100, 100, 255].repeat(4);
+        let effects = Effects{bloom: true, ..Effects::NONE};
+        let output = SoftwarePostFx::new().render(&gray, 2, 2, effects).to_vec();
+        assert!(output.chunks_exact(4).all(|pixel| pixel == [135, 135, 135, 255]));
+    }
+
+    #[test]
+    fn curvature_blacks_out_corners(){
+        let white = [255; 64 * 32 * 4];
+        let effects = Effects{curvature: true, ..Effects::NONE};
+        let output = SoftwarePostFx::new().render(&white, 64, 32, effects).to_vec();
+        assert_eq!(pixel(&output, 64, 0, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(&output, 64, 128, 64), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn parse_effects(){
+        assert_eq!("none".parse(), Ok(Effects::NONE));
+        assert_eq!("crt".parse(), Ok(Effects::CRT));
+        assert_eq!("grid,crt".parse(), Ok(Effects{grid: true, ..Effects::CRT}));
+        assert!("glow".parse::<Effects>().is_err());
+        assert_eq!(Effects::CRT.to_string(), "scanlines,bloom,curvature");
+    }
+}
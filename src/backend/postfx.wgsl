@@ -0,0 +1,73 @@
+// GPU version of the effects in postfx.rs, keep the constants in sync.
+const SUBPIXELS: f32 = 4.0;
+const SCANLINE_SHADE: f32 = 0.55;
+const GRID_SHADE: f32 = 0.6;
+const BLOOM_STRENGTH: f32 = 0.35;
+const CURVATURE: f32 = 0.08;
+
+struct Settings{
+    texture_size: vec2<f32>,
+    padding: vec2<f32>,
+    // Scanlines, grid, bloom and curvature, 1.0 when switched on.
+    effects: vec4<f32>,
+}
+
+@group(0) @binding(0) var screen: texture_2d<f32>;
+@group(0) @binding(1) var screen_sampler: sampler;
+@group(0) @binding(2) var<uniform> settings: Settings;
+
+struct VertexOutput{
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle that covers the viewport, uv runs 0..1 across it.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput{
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn warp(uv: vec2<f32>) -> vec2<f32>{
+    let centered = uv * 2.0 - 1.0;
+    let stretched = centered * (1.0 + CURVATURE * dot(centered, centered));
+    return (stretched + 1.0) / 2.0;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
+    var uv = in.uv;
+    if settings.effects.w > 0.5{
+        uv = warp(uv);
+        if any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0)){
+            return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        }
+    }
+
+    var color = textureSampleLevel(screen, screen_sampler, uv, 0.0).rgb;
+    let sub = floor(fract(uv * settings.texture_size) * SUBPIXELS);
+    var shade = 1.0;
+    if settings.effects.y > 0.5 && (sub.x == SUBPIXELS - 1.0 || sub.y == SUBPIXELS - 1.0){
+        shade *= GRID_SHADE;
+    }
+    if settings.effects.x > 0.5 && sub.y % 2.0 == 1.0{
+        shade *= SCANLINE_SHADE;
+    }
+    color *= shade;
+
+    if settings.effects.z > 0.5{
+        let texel = 1.0 / settings.texture_size;
+        var glow = vec3<f32>(0.0);
+        for (var dy = -1; dy <= 1; dy++){
+            for (var dx = -1; dx <= 1; dx++){
+                let offset = vec2<f32>(f32(dx), f32(dy)) * texel;
+                glow += textureSampleLevel(screen, screen_sampler, uv + offset, 0.0).rgb;
+            }
+        }
+        color += glow / 9.0 * BLOOM_STRENGTH;
+    }
+    return vec4<f32>(min(color, vec3<f32>(1.0)), 1.0);
+}
@@ -0,0 +1,140 @@
+use pixels::wgpu;
+use pixels::{Pixels, PixelsContext};
+
+use crate::backend::postfx::Effects;
+
+// Texture size, padding and the four effect switches, as the shader's `Settings`.
+const SETTINGS_SIZE: u64 = 8 * 4;
+
+// Runs postfx.wgsl over the pixels texture in place of the default scaling
+// renderer, drawing into the same letterboxed rectangle.
+pub struct GpuPostFx{
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    settings: wgpu::Buffer,
+}
+
+impl GpuPostFx{
+    pub fn new(pixels: &Pixels) -> Self{
+        let device = pixels.device();
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor{
+            label: Some("postfx"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("postfx.wgsl").into()),
+        });
+
+        let fragment_entry = |binding, ty| wgpu::BindGroupLayoutEntry{
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty,
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor{
+            label: Some("postfx"),
+            entries: &[
+                fragment_entry(0, wgpu::BindingType::Texture{
+                    sample_type: wgpu::TextureSampleType::Float{filterable: true},
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                }),
+                fragment_entry(1, wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)),
+                fragment_entry(2, wgpu::BindingType::Buffer{
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                }),
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor{
+            label: Some("postfx"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor{
+            label: Some("postfx"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState{
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState{
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState{
+                    format: pixels.render_texture_format(),
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor{
+            label: Some("postfx"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let settings = device.create_buffer(&wgpu::BufferDescriptor{
+            label: Some("postfx"),
+            size: SETTINGS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self{pipeline, bind_group_layout, sampler, settings}
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, context: &PixelsContext, effects: Effects, border: wgpu::Color){
+        let switch = |on: bool| if on { 1.0 } else { 0.0 };
+        let settings: [f32; 8] = [
+            context.texture_extent.width as f32,
+            context.texture_extent.height as f32,
+            0.0,
+            0.0,
+            switch(effects.scanlines),
+            switch(effects.grid),
+            switch(effects.bloom),
+            switch(effects.curvature),
+        ];
+        let bytes: Vec<u8> = settings.iter().flat_map(|value| value.to_ne_bytes()).collect();
+        context.queue.write_buffer(&self.settings, 0, &bytes);
+
+        // The pixels texture is recreated when a program switches resolution,
+        // so the bind group is built fresh every frame.
+        let view = context.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor{
+            label: Some("postfx"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry{binding: 0, resource: wgpu::BindingResource::TextureView(&view)},
+                wgpu::BindGroupEntry{binding: 1, resource: wgpu::BindingResource::Sampler(&self.sampler)},
+                wgpu::BindGroupEntry{binding: 2, resource: self.settings.as_entire_binding()},
+            ],
+        });
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor{
+            label: Some("postfx"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment{
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations{
+                    load: wgpu::LoadOp::Clear(border),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        let (x, y, width, height) = context.scaling_renderer.clip_rect();
+        pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+
+use crate::chip8::cpu::PROGRAM_START;
+use crate::chip8::error::AsmError;
+use crate::chip8::symbols::SymbolTable;
+
+const MEMORY_END: usize = 0x10000;
+
+pub struct Assembly{
+    pub rom: Vec<u8>,
+    pub symbols: SymbolTable,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a>{
+    text: &'a str,
+    line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FixupKind{
+    // Low 12 bits of an instruction, e.g. the NNN of 1NNN.
+    Address,
+    // A full 16-bit word, the operand of `i := long`.
+    Long,
+}
+
+struct Fixup{
+    position: usize,
+    label: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+// Assembles a subset of Octo: labels, `:const`, `:alias`, `:org`, `:byte`,
+// raw bytes and every instruction the Cpu executes. Control structures and
+// `:macro` are not supported. If `main` is defined anywhere but at the very
+// start, a `jump main` is placed at 0x200 the way Octo does.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError>{
+    let tokens = tokenize(source);
+    let mut assembler = Assembler{
+        tokens,
+        position: 0,
+        rom: Vec::new(),
+        address: PROGRAM_START,
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+    };
+    assembler.run()?;
+
+    let mut symbols = SymbolTable::new();
+    for (name, &address) in &assembler.labels{
+        symbols.insert(name, address);
+    }
+    Ok(Assembly{rom: assembler.rom, symbols})
+}
+
+fn tokenize(source: &str) -> Vec<Token<'_>>{
+    source.lines()
+        .enumerate()
+        .flat_map(|(idx, line)| {
+            let code = line.split('#').next().unwrap_or("");
+            code.split_whitespace().map(move |text| Token{text, line: idx + 1})
+        })
+        .collect()
+}
+
+fn parse_number(text: &str) -> Option<i64>{
+    let (negative, digits) = match text.strip_prefix('-'){
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")){
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")){
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8>{
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1{
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+struct Assembler<'a>{
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    rom: Vec<u8>,
+    address: usize,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    fixups: Vec<Fixup>,
+}
+
+impl<'a> Assembler<'a>{
+    fn run(&mut self) -> Result<(), AsmError>{
+        let starts_with_main = matches!(self.tokens.as_slice(), [first, second, ..] if first.text == ":" && second.text == "main");
+        let defines_main = self.tokens.windows(2).any(|pair| pair[0].text == ":" && pair[1].text == "main");
+        if defines_main && !starts_with_main{
+            self.emit_address(0x1000, "main", 1)?;
+        }
+
+        while self.position < self.tokens.len(){
+            self.statement()?;
+        }
+
+        for fixup in std::mem::take(&mut self.fixups){
+            let Some(&address) = self.labels.get(&fixup.label) else {
+                return Err(AsmError{line: fixup.line, message: format!("undefined label `{}`", fixup.label)});
+            };
+            match fixup.kind{
+                FixupKind::Address => {
+                    if address > 0xFFF{
+                        return Err(AsmError{
+                            line: fixup.line,
+                            message: format!("label `{}` at {address:#06X} is out of reach of a 12-bit address", fixup.label),
+                        });
+                    }
+                    self.rom[fixup.position] |= (address >> 8) as u8;
+                    self.rom[fixup.position + 1] = address as u8;
+                },
+                FixupKind::Long => {
+                    self.rom[fixup.position..fixup.position + 2].copy_from_slice(&address.to_be_bytes());
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn error<T>(&self, line: usize, message: String) -> Result<T, AsmError>{
+        Err(AsmError{line, message})
+    }
+
+    fn next(&mut self, what: &str) -> Result<Token<'a>, AsmError>{
+        match self.tokens.get(self.position){
+            Some(&token) => {
+                self.position += 1;
+                Ok(token)
+            },
+            None => {
+                let line = self.tokens.last().map_or(1, |token| token.line);
+                self.error(line, format!("expected {what} at the end of the file"))
+            },
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str>{
+        self.tokens.get(self.position).map(|token| token.text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError>{
+        let token = self.next(&format!("`{text}`"))?;
+        if token.text != text{
+            return self.error(token.line, format!("expected `{text}`, found `{}`", token.text));
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, line: usize, byte: u8) -> Result<(), AsmError>{
+        if self.address >= MEMORY_END{
+            return self.error(line, "program does not fit in memory".to_string());
+        }
+        let offset = self.address - PROGRAM_START;
+        if self.rom.len() <= offset{
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.address += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, line: usize, opcode: u16) -> Result<(), AsmError>{
+        let [high, low] = opcode.to_be_bytes();
+        self.emit_byte(line, high)?;
+        self.emit_byte(line, low)
+    }
+
+    // Emits `prefix | NNN` where NNN is a label, constant or number.
+    fn emit_address(&mut self, prefix: u16, operand: &str, line: usize) -> Result<(), AsmError>{
+        let address = match self.value(operand){
+            Some(value) => self.check_range(line, operand, value, 0xFFF)? as u16,
+            None => {
+                self.fixups.push(Fixup{
+                    position: self.address - PROGRAM_START,
+                    label: operand.to_string(),
+                    kind: FixupKind::Address,
+                    line,
+                });
+                0
+            },
+        };
+        self.emit(line, prefix | address)
+    }
+
+    // Known labels, constants and numbers. Anything else may be a label defined later.
+    fn value(&self, text: &str) -> Option<i64>{
+        parse_number(text)
+            .or_else(|| self.consts.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|&address| address as i64))
+    }
+
+    fn check_range(&self, line: usize, text: &str, value: i64, max: i64) -> Result<i64, AsmError>{
+        if !(0..=max).contains(&value){
+            return self.error(line, format!("`{text}` is out of range, expected 0 to {max:#X}"));
+        }
+        Ok(value)
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError>{
+        let token = self.next("a register")?;
+        self.register_of(token).ok_or_else(|| AsmError{line: token.line, message: format!("expected a register, found `{}`", token.text)})
+    }
+
+    fn register_of(&self, token: Token) -> Option<u8>{
+        parse_register(token.text).or_else(|| self.aliases.get(token.text).copied())
+    }
+
+    // A byte operand. Negative values down to -128 wrap, so `v0 += -1` works.
+    fn byte(&mut self) -> Result<u8, AsmError>{
+        let token = self.next("a byte")?;
+        match parse_number(token.text).or_else(|| self.consts.get(token.text).copied()){
+            Some(value) if (-128..=255).contains(&value) => Ok(value as u8),
+            Some(_) => self.error(token.line, format!("`{}` does not fit in a byte", token.text)),
+            None => self.error(token.line, format!("expected a number, found `{}`", token.text)),
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError>{
+        let token = self.next("a number")?;
+        match parse_number(token.text).or_else(|| self.consts.get(token.text).copied()){
+            Some(value) => Ok(self.check_range(token.line, token.text, value, 0xF)? as u8),
+            None => self.error(token.line, format!("expected a number, found `{}`", token.text)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError>{
+        let token = self.next("a statement")?;
+        let line = token.line;
+
+        match token.text{
+            ":" => {
+                let name = self.next("a label name")?;
+                if self.labels.contains_key(name.text) || self.consts.contains_key(name.text){
+                    return self.error(name.line, format!("`{}` is already defined", name.text));
+                }
+                if self.address > 0xFFFF{
+                    return self.error(name.line, "label is past the end of memory".to_string());
+                }
+                self.labels.insert(name.text.to_string(), self.address as u16);
+            },
+            ":const" => {
+                let name = self.next("a constant name")?;
+                let value = self.next("a value")?;
+                let Some(number) = parse_number(value.text).or_else(|| self.consts.get(value.text).copied()) else {
+                    return self.error(value.line, format!("expected a number, found `{}`", value.text));
+                };
+                self.consts.insert(name.text.to_string(), number);
+            },
+            ":alias" => {
+                let name = self.next("an alias name")?;
+                let register = self.register()?;
+                self.aliases.insert(name.text.to_string(), register);
+            },
+            ":org" => {
+                let value = self.next("an address")?;
+                let Some(address) = self.value(value.text) else {
+                    return self.error(value.line, format!("expected an address, found `{}`", value.text));
+                };
+                let address = self.check_range(value.line, value.text, address, MEMORY_END as i64 - 1)? as usize;
+                if address < PROGRAM_START{
+                    return self.error(value.line, format!("`{}` is below the program start {PROGRAM_START:#05X}", value.text));
+                }
+                self.address = address;
+            },
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(line, byte)?;
+            },
+            ":call" => {
+                let target = self.next("a call target")?;
+                self.emit_address(0x2000, target.text, target.line)?;
+            },
+            "clear" => self.emit(line, 0x00E0)?,
+            "return" => self.emit(line, 0x00EE)?,
+            "scroll-right" => self.emit(line, 0x00FB)?,
+            "scroll-left" => self.emit(line, 0x00FC)?,
+            "exit" => self.emit(line, 0x00FD)?,
+            "lores" => self.emit(line, 0x00FE)?,
+            "hires" => self.emit(line, 0x00FF)?,
+            "audio" => self.emit(line, 0xF002)?,
+            "scroll-down" => {
+                let rows = self.nibble()?;
+                self.emit(line, 0x00C0 | rows as u16)?;
+            },
+            "scroll-up" => {
+                let rows = self.nibble()?;
+                self.emit(line, 0x00D0 | rows as u16)?;
+            },
+            "jump" | "jump0" => {
+                let target = self.next("a jump target")?;
+                let prefix = if token.text == "jump" { 0x1000 } else { 0xB000 };
+                self.emit_address(prefix, target.text, target.line)?;
+            },
+            "plane" => {
+                let planes = self.nibble()?;
+                if planes > 3{
+                    return self.error(line, format!("plane mask {planes} is out of range, expected 0 to 3"));
+                }
+                self.emit(line, 0xF001 | (planes as u16) << 8)?;
+            },
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let height = self.nibble()? as u16;
+                self.emit(line, 0xD000 | x << 8 | y << 4 | height)?;
+            },
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = self.register()? as u16;
+                let low = match token.text{
+                    "bcd" => 0x33,
+                    "saveflags" => 0x75,
+                    _ => 0x85,
+                };
+                self.emit(line, 0xF000 | x << 8 | low)?;
+            },
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-"){
+                    self.position += 1;
+                    let y = self.register()? as u16;
+                    let low = if token.text == "save" { 0x2 } else { 0x3 };
+                    self.emit(line, 0x5000 | x << 8 | y << 4 | low)?;
+                } else {
+                    let low = if token.text == "save" { 0x55 } else { 0x65 };
+                    self.emit(line, 0xF000 | x << 8 | low)?;
+                }
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()? as u16;
+                let low = match token.text{
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(line, 0xF000 | x << 8 | low)?;
+            },
+            "if" => self.condition(line)?,
+            "i" => self.index(line)?,
+            text => {
+                if let Some(x) = self.register_of(token){
+                    self.assignment(line, x as u16)?;
+                } else if let Some(value) = parse_number(text).or_else(|| self.consts.get(text).copied()){
+                    if !(-128..=255).contains(&value){
+                        return self.error(line, format!("`{text}` does not fit in a byte"));
+                    }
+                    self.emit_byte(line, value as u8)?;
+                } else if text.starts_with(':'){
+                    return self.error(line, format!("unsupported directive `{text}`"));
+                } else {
+                    // A bare name calls the subroutine with that label.
+                    self.emit_address(0x2000, text, line)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // `if vx == nn then`, `if vx != vy then`, `if vx key then`, `if vx -key then`.
+    fn condition(&mut self, line: usize) -> Result<(), AsmError>{
+        let x = self.register()? as u16;
+        let operator = self.next("a comparison")?;
+        let opcode = match operator.text{
+            "key" => 0xE0A1 | x << 8,
+            "-key" => 0xE09E | x << 8,
+            "==" | "!=" => {
+                let operand = self.next("a value or register")?;
+                let equal = operator.text == "==";
+                if let Some(y) = self.register_of(operand){
+                    let base = if equal { 0x9000 } else { 0x5000 };
+                    base | x << 8 | (y as u16) << 4
+                } else {
+                    self.position -= 1;
+                    let byte = self.byte()? as u16;
+                    let base = if equal { 0x4000 } else { 0x3000 };
+                    base | x << 8 | byte
+                }
+            },
+            text => return self.error(operator.line, format!("unsupported comparison `{text}`, expected ==, !=, key or -key")),
+        };
+        self.expect("then")?;
+        self.emit(line, opcode)
+    }
+
+    // `i := nnn`, `i := long nnnn`, `i := hex vx`, `i := bighex vx`, `i += vx`.
+    fn index(&mut self, line: usize) -> Result<(), AsmError>{
+        let operator = self.next("`:=` or `+=`")?;
+        match operator.text{
+            "+=" => {
+                let x = self.register()? as u16;
+                self.emit(line, 0xF01E | x << 8)
+            },
+            ":=" => {
+                let operand = self.next("an address")?;
+                match operand.text{
+                    "hex" | "bighex" => {
+                        let x = self.register()? as u16;
+                        let low = if operand.text == "hex" { 0x29 } else { 0x30 };
+                        self.emit(line, 0xF000 | x << 8 | low)
+                    },
+                    "long" => {
+                        let target = self.next("an address")?;
+                        self.emit(line, 0xF000)?;
+                        match self.value(target.text){
+                            Some(value) => {
+                                let address = self.check_range(target.line, target.text, value, 0xFFFF)?;
+                                self.emit(line, address as u16)
+                            },
+                            None => {
+                                self.fixups.push(Fixup{
+                                    position: self.address - PROGRAM_START,
+                                    label: target.text.to_string(),
+                                    kind: FixupKind::Long,
+                                    line: target.line,
+                                });
+                                self.emit(line, 0)
+                            },
+                        }
+                    },
+                    target => self.emit_address(0xA000, target, operand.line),
+                }
+            },
+            text => self.error(operator.line, format!("expected `:=` or `+=` after `i`, found `{text}`")),
+        }
+    }
+
+    fn assignment(&mut self, line: usize, x: u16) -> Result<(), AsmError>{
+        let operator = self.next("an operator")?;
+        let operand = self.next("an operand")?;
+        let y = self.register_of(operand).map(|y| (y as u16) << 4);
+
+        let opcode = match (operator.text, y){
+            (":=", Some(y)) => 0x8000 | y,
+            ("|=", Some(y)) => 0x8001 | y,
+            ("&=", Some(y)) => 0x8002 | y,
+            ("^=", Some(y)) => 0x8003 | y,
+            ("+=", Some(y)) => 0x8004 | y,
+            ("-=", Some(y)) => 0x8005 | y,
+            (">>=", Some(y)) => 0x8006 | y,
+            ("=-", Some(y)) => 0x8007 | y,
+            ("<<=", Some(y)) => 0x800E | y,
+            (":=", None) => match operand.text{
+                "delay" => 0xF007,
+                "key" => 0xF00A,
+                "random" => 0xC000 | self.byte()? as u16,
+                _ => {
+                    self.position -= 1;
+                    0x6000 | self.byte()? as u16
+                },
+            },
+            ("+=", None) => {
+                self.position -= 1;
+                0x7000 | self.byte()? as u16
+            },
+            (operator, _) => {
+                return self.error(line, format!("`v{x:x} {operator} {}` is not an instruction", operand.text));
+            },
+        };
+        self.emit(line, opcode | x << 8)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::fs;
+
+    use super::*;
+    use crate::chip8::disasm::{Listing, Syntax};
+
+    fn error(source: &str) -> AsmError{
+        match assemble(source){
+            Ok(_) => panic!("`{source}` assembled"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn disassembly_round_trips(){
+        for entry in fs::read_dir("programs").unwrap(){
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "ch8"){
+                continue;
+            }
+            let rom = fs::read(&path).unwrap();
+            let source = Listing::new(&rom, PROGRAM_START as u16, Syntax::Octo).to_string();
+            let assembly = assemble(&source).unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+            assert!(assembly.rom == rom, "{} changed after a round trip", path.display());
+        }
+    }
+
+    #[test]
+    fn labels_and_jumps(){
+        let assembly = assemble(": main\n  v0 := 0x12\n  i := sprite\n  jump main\n: sprite\n  0xFF 0x81\n").unwrap();
+        assert_eq!(assembly.rom, [0x60, 0x12, 0xA2, 0x06, 0x12, 0x00, 0xFF, 0x81]);
+    }
+
+    #[test]
+    fn undefined_label(){
+        let err = error("v0 := 1\njump nowhere\n");
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("nowhere"), "{}", err.message);
+    }
+
+    #[test]
+    fn label_out_of_12_bit_range(){
+        let err = error(": main\njump far\n:org 0x1000\n: far\n");
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("12-bit"), "{}", err.message);
+    }
+
+    #[test]
+    fn byte_out_of_range(){
+        assert_eq!(error("v0 := 256\n").line, 1);
+        assert_eq!(error("\nv1 += 0x100\n").line, 2);
+        assert_eq!(error(":byte 300\n").line, 1);
+    }
+}
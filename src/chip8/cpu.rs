@@ -1,38 +1,31 @@
 use std::fs;
+use std::ops::Range;
 use std::path::Path;
-use std::time::{Duration, Instant};
 
 use rand::Rng;
 
 use crate::backend::backend::{Backend, Keys};
+use crate::chip8::display::Display;
+use crate::chip8::error::{CpuFault, FaultKind, LoadError, LoadWarning};
+use crate::chip8::font::{Font, FontSlot, CHIP48_FONT, DEFAULT_BIG_FONT_ADDRESS, DEFAULT_FONT_ADDRESS, SCHIP_BIG_FONT};
+use crate::chip8::op::{Instruction, Op, LONG_LOAD_OPCODE};
+use crate::chip8::quirks::{LoadStoreQuirk, Quirks};
+use crate::chip8::snapshot::Snapshot;
+use crate::chip8::trace::{TraceEntry, Tracer};
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
-
-const HEX_SPRITE_LEN: u16 = 10;
-const HEX_SPRITE_START: u16 = 0x50;
-const HEX_SPRITES: [u8; 80] = [
-0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-0x20, 0x60, 0x20, 0x20, 0x70, // 1
-0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-];
+pub const PROGRAM_START: usize = 0x200;
+pub const MAX_ROM_SIZE: usize = 4096 - PROGRAM_START;
+
+const DEFAULT_PITCH: u8 = 64;
 
 pub struct Cpu{
-    memory: [u8; 4096],
-    framebuffer: [u8; WIDTH * HEIGHT],
+    memory: Vec<u8>,
+    display: Display,
+    // Set when the screen changed since it was last presented.
+    display_dirty: bool,
+    planes: u8,
+    small_font: FontSlot,
+    big_font: FontSlot,
 
     registers: Registers,
     pc: u16,
@@ -41,10 +34,17 @@ pub struct Cpu{
     st: u8,
 
     register_to_save_key: Option<u8>,
+    rpl_flags: [u8; 16],
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    exited: bool,
+    load_warnings: Vec<LoadWarning>,
+    tracer: Option<Tracer>,
 
     stack: [u16; 16],
     sp: usize,
-    cycle_handler: CycleHandler,
+
+    quirks: Quirks,
 }
 
 
@@ -69,15 +69,8 @@ pub struct Registers{
     VF: u8,
 }
 
-fn xy_to_1d(x: u8, y: u8) -> usize{
-    let x = x as usize;
-    let y = y as usize;
-
-    x + y * WIDTH
-}
-
 impl Registers{
-    pub fn get_register_by_nibble<'a>(&'a mut self, nibble: u8) -> &'a mut u8{
+    pub fn get_register_by_nibble(&mut self, nibble: u8) -> &mut u8{
         match nibble{
             0x0 => &mut self.V0,
             0x1 => &mut self.V1,
@@ -108,49 +101,38 @@ impl Registers{
         let reg = self.get_register_by_nibble(nibble);
         *reg = value;
     }
-}
-
-pub struct CycleHandler{
-    last_tick: Instant,
-    tick_duration: Duration,
-
-    last_timer_update: Instant,
-    timer_update_duration: Duration,
-}
-
-pub struct Instruction{
-    opcode: [u8; 2]
-}
-
-impl Instruction{
-    pub fn get_nibble(&self, idx: usize) -> u8{
-        assert!(idx < 4);
-        let byte = self.opcode[idx / 2];
-        let result = if idx % 2 == 0{
-            (byte >> 4) & 0xF
-        } else {
-            byte & 0xF
-        };
-        result
-    }
 
-    pub fn get_address(&self) -> u16{
-        let instruction = self.get_u16_instruction();
-        instruction & 0xFFF
+    pub fn to_array(&self) -> [u8; 16]{
+        [
+            self.V0, self.V1, self.V2, self.V3, self.V4, self.V5, self.V6, self.V7,
+            self.V8, self.V9, self.VA, self.VB, self.VC, self.VD, self.VE, self.VF,
+        ]
     }
 
-    pub fn get_u16_instruction(&self) -> u16{
-        ((self.opcode[0] as u16) << 8) | (self.opcode[1] as u16)
+    pub fn from_array(values: [u8; 16]) -> Self{
+        let mut registers = Self::default();
+        for (nibble, value) in values.into_iter().enumerate(){
+            registers.set_register_value(nibble as u8, value);
+        }
+        registers
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome{
+    Executed,
+    Idle,
+    WaitingForKey,
+    Exited,
+}
 
 impl<> Cpu{
     pub fn new() -> Self{
-        let memory = [0; 4096];
-        let framebuffer = [0; 64 * 32];
-        let ticks_per_second = 700;
-        let timer_updates_per_second = 60;
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self{
+        let memory = vec![0; quirks.memory_size()];
         let registers = Registers::default();
 
         let mut cpu = Self{
@@ -162,295 +144,891 @@ impl<> Cpu{
             dt: 0,
             st: 0,
             register_to_save_key: None,
-            framebuffer,
-            cycle_handler: CycleHandler{
-                tick_duration: Duration::from_secs_f64(1.0 / ticks_per_second as f64),
-                last_tick: Instant::now(),
-                last_timer_update: Instant::now(),
-                timer_update_duration: Duration::from_secs_f64(1.0 / timer_updates_per_second as f64),
-            },
-            pc: 0x200,
+            rpl_flags: [0; 16],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            exited: false,
+            load_warnings: Vec::new(),
+            tracer: None,
+            display: Display::new(),
+            display_dirty: true,
+            planes: 1,
+            small_font: FontSlot{address: DEFAULT_FONT_ADDRESS, glyph_len: CHIP48_FONT.glyph_len},
+            big_font: FontSlot{address: DEFAULT_BIG_FONT_ADDRESS, glyph_len: SCHIP_BIG_FONT.glyph_len},
+            pc: PROGRAM_START as u16,
+            quirks,
         };
-        cpu.load_rom();
+        cpu.load_font(&CHIP48_FONT, DEFAULT_FONT_ADDRESS).unwrap();
+        cpu.load_big_font(&SCHIP_BIG_FONT, DEFAULT_BIG_FONT_ADDRESS).unwrap();
         cpu
     }
 }
 
+impl Default for Cpu{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
 impl Cpu{
-    pub fn load_rom(&mut self){
-        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("programs/space_invaders.ch8");
-        let rom_data = fs::read(&path).expect("Failed to read ROM file");
+    pub fn load_rom_bytes(&mut self, rom_data: &[u8]) -> Result<(), LoadError>{
+        if rom_data.is_empty(){
+            return Err(LoadError::Empty{path: None});
+        }
+        if rom_data.len() > self.max_rom_size(){
+            return Err(LoadError::TooLarge{path: None, size: rom_data.len(), max: self.max_rom_size()});
+        }
+
+        self.load_warnings.clear();
+        if !rom_data.len().is_multiple_of(2){
+            self.load_warnings.push(LoadWarning::OddLength{size: rom_data.len()});
+        }
 
-        let start_address = 0x200;
-        let end_address = start_address + rom_data.len();
-        self.memory[start_address..end_address].copy_from_slice(&rom_data);
+        let end_address = PROGRAM_START + rom_data.len();
+        self.memory[PROGRAM_START..end_address].copy_from_slice(rom_data);
+        Ok(())
     }
 
-    pub fn load_hex_sprites(&mut self){
-        self.memory[HEX_SPRITE_START as usize..0x9F].copy_from_slice(&HEX_SPRITES);
+    pub fn load_rom_from_path<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoadError>{
+        let path = path.as_ref();
+        let rom_data = fs::read(path).map_err(|source| LoadError::Unreadable{
+            path: path.to_path_buf(),
+            source,
+        })?;
+        self.load_rom_bytes(&rom_data).map_err(|err| match err{
+            LoadError::Empty{..} => LoadError::Empty{path: Some(path.to_path_buf())},
+            LoadError::TooLarge{size, max, ..} => LoadError::TooLarge{path: Some(path.to_path_buf()), size, max},
+            err => err,
+        })
     }
 
-    pub fn update_timers(&mut self){
-        let now = Instant::now();
+    pub fn max_rom_size(&self) -> usize{
+        self.memory.len() - PROGRAM_START
+    }
 
-        if now.duration_since(self.cycle_handler.last_timer_update) >= self.cycle_handler.timer_update_duration{
-            if self.dt > 0{
-                self.dt -= 1;
-            }
+    pub fn load_warnings(&self) -> &[LoadWarning]{
+        &self.load_warnings
+    }
 
-            if self.st > 0{
-                self.st -= 1;
-            }
-            self.cycle_handler.last_timer_update = now;
+    pub fn quirks(&self) -> Quirks{
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks){
+        self.quirks = quirks;
+        self.memory.resize(quirks.memory_size(), 0);
+    }
+
+    pub fn load_font(&mut self, font: &Font, address: u16) -> Result<(), LoadError>{
+        self.small_font = self.place_font(font, address)?;
+        Ok(())
+    }
+
+    pub fn load_big_font(&mut self, font: &Font, address: u16) -> Result<(), LoadError>{
+        self.big_font = self.place_font(font, address)?;
+        Ok(())
+    }
+
+    fn place_font(&mut self, font: &Font, address: u16) -> Result<FontSlot, LoadError>{
+        let start = address as usize;
+        let end = start + font.data.len();
+        if end > self.memory.len(){
+            return Err(LoadError::FontOutOfBounds{address, len: font.data.len()});
+        }
+        self.memory[start..end].copy_from_slice(font.data);
+        Ok(FontSlot{address, glyph_len: font.glyph_len})
+    }
+
+    pub fn display(&self) -> &Display{
+        &self.display
+    }
+
+    pub fn snapshot(&self) -> Snapshot{
+        Snapshot{
+            quirks: self.quirks,
+            memory: self.memory.clone(),
+            display_width: self.display.width(),
+            display_height: self.display.height(),
+            display_pixels: self.display.pixels().to_vec(),
+            planes: self.planes,
+            small_font: self.small_font,
+            big_font: self.big_font,
+            registers: self.registers.to_array(),
+            pc: self.pc,
+            i: self.i,
+            dt: self.dt,
+            st: self.st,
+            register_to_save_key: self.register_to_save_key,
+            rpl_flags: self.rpl_flags,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            exited: self.exited,
+            stack: self.stack,
+            sp: self.sp,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot){
+        self.quirks = snapshot.quirks;
+        self.memory.clone_from(&snapshot.memory);
+        self.display = Display::from_parts(snapshot.display_width, snapshot.display_height, snapshot.display_pixels.clone());
+        self.planes = snapshot.planes;
+        self.small_font = snapshot.small_font;
+        self.big_font = snapshot.big_font;
+        self.registers = Registers::from_array(snapshot.registers);
+        self.pc = snapshot.pc;
+        self.i = snapshot.i;
+        self.dt = snapshot.dt;
+        self.st = snapshot.st;
+        self.register_to_save_key = snapshot.register_to_save_key;
+        self.rpl_flags = snapshot.rpl_flags;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.pitch = snapshot.pitch;
+        self.exited = snapshot.exited;
+        self.stack = snapshot.stack;
+        self.sp = snapshot.sp;
+        self.display_dirty = true;
+    }
+
+    // Pushes the screen and audio pattern to a backend that may be out of
+    // date, e.g. after a restore.
+    pub fn sync_backend<B: Backend>(&mut self, backend: &mut B){
+        self.present(backend);
+        backend.set_audio_pattern(self.audio_pattern, self.pitch);
+    }
+
+    pub fn pc(&self) -> u16{
+        self.pc
+    }
+
+    // The instruction at PC, None if it is invalid or runs off the end of memory.
+    pub fn next_op(&self) -> Option<Op>{
+        let pc = self.pc as usize;
+        let bytes = self.memory.get(pc..pc + 2)?;
+        Instruction::from(u16::from_be_bytes([bytes[0], bytes[1]])).decode().ok()
+    }
+
+    pub fn i(&self) -> u16{
+        self.i
+    }
+
+    pub fn registers(&self) -> [u8; 16]{
+        self.registers.to_array()
+    }
+
+    pub fn dt(&self) -> u8{
+        self.dt
+    }
+
+    pub fn st(&self) -> u8{
+        self.st
+    }
+
+    pub fn memory(&self) -> &[u8]{
+        &self.memory
+    }
+
+    // Return addresses of the active calls, innermost last.
+    pub fn stack(&self) -> &[u16]{
+        &self.stack[..self.sp]
+    }
+
+    pub fn planes(&self) -> u8{
+        self.planes
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>){
+        self.tracer = tracer;
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; 16]{
+        &self.rpl_flags
+    }
+
+    pub fn has_exited(&self) -> bool{
+        self.exited
+    }
+
+    pub fn tick_timers(&mut self){
+        if self.dt > 0{
+            self.dt -= 1;
+        }
+
+        if self.st > 0{
+            self.st -= 1;
         }
     }
 
     pub fn waiting_key_pressed(&mut self, key: Keys){
-        println!("KEY PRESS WAITED");
-        let value: u8 = key.into();
-        let register = self.registers.get_register_by_nibble(self.register_to_save_key.unwrap());
-        self.register_to_save_key = None;
-        *register = value;
+        let Some(nibble) = self.register_to_save_key.take() else {
+            return;
+        };
+        self.registers.set_register_value(nibble, key.into());
     }
 
-    pub fn tick<B: Backend>(&mut self, backend: &mut B){
-        let now = Instant::now();
+    pub fn sound_active(&self) -> bool{
+        self.st > 0
+    }
+
+    pub fn is_waiting_for_key(&self) -> bool{
+        self.register_to_save_key.is_some()
+    }
 
-        if now.duration_since(self.cycle_handler.last_tick) >= self.cycle_handler.tick_duration{
-            self.fetch(backend);
-            self.cycle_handler.last_tick = Instant::now();
+    pub fn step<B: Backend>(&mut self, backend: &mut B) -> Result<StepOutcome, CpuFault>{
+        if self.exited{
+            return Ok(StepOutcome::Exited);
+        }
+        if self.is_waiting_for_key(){
+            return Ok(StepOutcome::WaitingForKey);
         }
+        self.fetch(backend)
     }
 
-    fn fetch<B: Backend>(&mut self, backend: &mut B){
+    fn fetch<B: Backend>(&mut self, backend: &mut B) -> Result<StepOutcome, CpuFault>{
         let pc = self.pc as usize;
+        if pc + 1 >= self.memory.len(){
+            let fault = CpuFault{pc: self.pc, opcode: 0, kind: FaultKind::PcOutOfBounds};
+            if let Some(tracer) = &mut self.tracer{
+                tracer.fault(&fault);
+            }
+            return Err(fault);
+        }
+
         let instruction = Instruction{opcode: self.memory[pc..pc + 2].try_into().unwrap()};
-        self.pc += 2;
-        self.decode(backend, instruction);
+        let opcode = instruction.get_u16_instruction();
+        let registers_before = self.tracer.is_some().then(|| self.registers.to_array());
+        self.pc = self.pc.wrapping_add(2);
+        let result = instruction.decode()
+            .ok()
+            .filter(|op| self.is_supported(op))
+            .ok_or(FaultKind::UnknownOpcode)
+            .and_then(|op| self.execute(backend, op))
+            .map_err(|kind| CpuFault{pc: pc as u16, opcode, kind});
+        if let Some(before) = registers_before{
+            self.trace(pc as u16, opcode, before, result.as_ref().err());
+        }
+        result?;
+
+        if self.exited{
+            Ok(StepOutcome::Exited)
+        } else if self.is_waiting_for_key(){
+            Ok(StepOutcome::WaitingForKey)
+        } else {
+            Ok(StepOutcome::Executed)
+        }
     }
 
-    fn decode<B: Backend>(&mut self, backend: &mut B, instruction: Instruction){
-        let first_nibble = instruction.get_nibble(0);
+    fn trace(&mut self, pc: u16, opcode: u16, before: [u8; 16], fault: Option<&CpuFault>){
+        let after = self.registers.to_array();
+        let changed = (0..16u8)
+            .filter(|&register| before[register as usize] != after[register as usize])
+            .map(|register| (register, before[register as usize], after[register as usize]))
+            .collect();
+        let entry = TraceEntry{pc, opcode, changed, i: self.i, dt: self.dt, st: self.st};
 
-        match first_nibble{
-            0x0 => {
-                if instruction.get_address() == 0x0E0{
-                    self.framebuffer = [0; 64 * 32]
-                } else {
-                    self.sp -= 1;
-                    self.pc = self.stack[self.sp];
+        if let Some(tracer) = &mut self.tracer{
+            tracer.record(entry);
+            if let Some(fault) = fault{
+                tracer.fault(fault);
+            }
+        }
+    }
+
+    // Opcodes from extensions the quirks don't enable are as unknown as any other.
+    fn is_supported(&self, op: &Op) -> bool{
+        (!op.is_schip() || self.quirks.schip_opcodes) && (!op.is_xo_chip() || self.quirks.xo_chip_opcodes)
+    }
+
+    fn memory_range(&self, start: u16, len: usize) -> Result<Range<usize>, FaultKind>{
+        let start = start as usize;
+        let end = start + len;
+        if end > self.memory.len(){
+            return Err(FaultKind::MemoryOutOfBounds{address: start.max(self.memory.len())});
+        }
+        Ok(start..end)
+    }
+
+    fn apply_load_store_quirk(&mut self, x: u8){
+        match self.quirks.load_store{
+            LoadStoreQuirk::Unchanged => (),
+            LoadStoreQuirk::IncrementByX => self.i = self.i.wrapping_add(x as u16),
+            LoadStoreQuirk::IncrementByXPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
+        }
+    }
+
+    // Skips the next instruction, which is 4 bytes long if it is an XO-CHIP long load.
+    fn skip_next(&mut self){
+        let pc = self.pc as usize;
+        let next = self.memory.get(pc..pc + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+        let len = if self.quirks.xo_chip_opcodes && next == Some(LONG_LOAD_OPCODE) { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(len);
+    }
+
+    fn selected_planes(&self) -> Vec<u8>{
+        [1, 2].into_iter().filter(|plane| self.planes & plane != 0).collect()
+    }
+
+    pub fn present<B: Backend>(&mut self, backend: &mut B){
+        backend.draw_frame(self.display.pixels(), self.display.width(), self.display.height());
+        self.display_dirty = false;
+    }
+
+    pub fn is_display_dirty(&self) -> bool{
+        self.display_dirty
+    }
+
+    // Hands the screen to the backend if it changed since the last call. Frontends
+    // call this once per host frame rather than on every instruction that draws.
+    pub fn present_if_dirty<B: Backend>(&mut self, backend: &mut B) -> bool{
+        if !self.display_dirty{
+            return false;
+        }
+        self.present(backend);
+        true
+    }
+
+    fn execute<B: Backend>(&mut self, backend: &mut B, op: Op) -> Result<(), FaultKind>{
+        let quirks = self.quirks;
+        match op{
+            Op::Clear => {
+                self.display.clear(self.planes);
+                self.display_dirty = true;
+            },
+            Op::Return => {
+                if self.sp == 0{
+                    return Err(FaultKind::StackUnderflow);
                 }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp];
             },
-            0x1 => {
-                let address = instruction.get_address();
-                self.pc = address;
+            Op::ScrollDown(rows) => {
+                self.display.scroll_down(rows as usize, self.planes);
+                self.display_dirty = true;
+            },
+            Op::ScrollUp(rows) => {
+                self.display.scroll_up(rows as usize, self.planes);
+                self.display_dirty = true;
+            },
+            Op::ScrollRight => {
+                self.display.scroll_right(4, self.planes);
+                self.display_dirty = true;
             },
-            0x2 => {
-                let address = instruction.get_address();
+            Op::ScrollLeft => {
+                self.display.scroll_left(4, self.planes);
+                self.display_dirty = true;
+            },
+            Op::Exit => self.exited = true,
+            Op::Lores => {
+                self.display.set_hires(false);
+                self.display_dirty = true;
+            },
+            Op::Hires => {
+                self.display.set_hires(true);
+                self.display_dirty = true;
+            },
+            Op::Jump(address) => self.pc = address,
+            Op::Call(address) => {
+                if self.sp == self.stack.len(){
+                    return Err(FaultKind::StackOverflow);
+                }
                 self.stack[self.sp] = self.pc;
                 self.sp += 1;
                 self.pc = address;
-            }
-            0x3 => {
-                let reg = self.registers.get_register_by_nibble(instruction.get_nibble(1));
-                let byte = instruction.opcode[1];
-                if *reg == byte{
-                    self.pc += 2;
+            },
+            Op::SkipIfEqualByte{x, byte} => {
+                if self.registers.get_register_value(x) == byte{
+                    self.skip_next();
                 }
             },
-            0x4 => {
-                let reg = self.registers.get_register_by_nibble(instruction.get_nibble(1));
-                let byte = instruction.opcode[1];
-                if *reg != byte{
-                    self.pc += 2;
+            Op::SkipIfNotEqualByte{x, byte} => {
+                if self.registers.get_register_value(x) != byte{
+                    self.skip_next();
                 }
-            }
-            0x5 => {
-                let regx = *self.registers.get_register_by_nibble(instruction.get_nibble(1));
-                let regy = *self.registers.get_register_by_nibble(instruction.get_nibble(2));
-                if regx == regy{
-                    self.pc += 2;
+            },
+            Op::SkipIfEqual{x, y} => {
+                if self.registers.get_register_value(x) == self.registers.get_register_value(y){
+                    self.skip_next();
                 }
-            }
-            0x6 => {
-                let reg_nibble = instruction.get_nibble(1);
-                let register = self.registers.get_register_by_nibble(reg_nibble);
-                *register = instruction.opcode[1];
-            },
-            0x7 => {
-                let reg_nibble = instruction.get_nibble(1);
-                let register = self.registers.get_register_by_nibble(reg_nibble);
-                let value = instruction.opcode[1];
-                *register = register.wrapping_add(value);
-            },
-            0x8 => {
-                let last_nibble = instruction.get_nibble(3);
-                let regy = self.registers.get_register_value(instruction.get_nibble(2));
-                let regx = self.registers.get_register_by_nibble(instruction.get_nibble(1));
-                match last_nibble{
-                    0 => *regx = regy,
-                    1 => {
-                        let or = *regx | regy;
-                        *regx = or;
-                    },
-                    2 => {
-                        let and = *regx & regy;
-                        *regx = and;
-                    },
-                    3 => *regx ^= regy,
-                    4 => {
-                        let (val, overflow) = (*regx).overflowing_add(regy);
-                        *regx = val;
-                        self.registers.VF = overflow as u8;
-                    },
-                    5 => {
-                        let carried = (*regx > regy) as u8;
-                        *regx = (*regx).wrapping_sub(regy);
-                        self.registers.VF = carried;
-                    },
-                    6 => {
-                        let should = (*regx & 0x1) != 0;
-                        *regx >>= 1;
-                        self.registers.VF = should as u8;
-                    },
-                    7 => {
-                        let should = (regy > *regx) as u8;
-                        *regx = regy - *regx;
-                        self.registers.VF = should;
-                    },
-                    8 => {
-                        let should = (*regx & 0x80) != 0;
-                        *regx <<= 1;
-                        self.registers.VF = should as u8;
-                    }
-                    _ => ()
+            },
+            Op::SkipIfNotEqual{x, y} => {
+                if self.registers.get_register_value(x) != self.registers.get_register_value(y){
+                    self.skip_next();
                 }
             },
-            0x9 => {
-                let regy = self.registers.get_register_value(instruction.get_nibble(2));
-                let regx = self.registers.get_register_by_nibble(instruction.get_nibble(1));
-                if *regx != regy{
-                    self.pc += 2;
+            Op::SaveRange{x, y} => {
+                let range = self.memory_range(self.i, x.abs_diff(y) as usize + 1)?;
+                for (offset, nibble) in register_range(x, y).enumerate(){
+                    self.memory[range.start + offset] = self.registers.get_register_value(nibble);
                 }
             },
-            0xA => {
-                let address = instruction.get_address();
-                self.i = address;
+            Op::LoadRange{x, y} => {
+                let range = self.memory_range(self.i, x.abs_diff(y) as usize + 1)?;
+                for (offset, nibble) in register_range(x, y).enumerate(){
+                    self.registers.set_register_value(nibble, self.memory[range.start + offset]);
+                }
             },
-            0xB => {
-                let address = instruction.get_address() + self.registers.V0 as u16;
-                self.pc = address;
+            Op::SetByte{x, byte} => self.registers.set_register_value(x, byte),
+            Op::AddByte{x, byte} => {
+                let register = self.registers.get_register_by_nibble(x);
+                *register = register.wrapping_add(byte);
             },
-            0xC => {
-                let mut rng = rand::rng();
-                let random_number: u8 = rng.random();
-                let val = instruction.opcode[1];
-                let vx = self.registers.get_register_by_nibble(instruction.get_nibble(1));
-                *vx = val & random_number;
-            }
-            0xD => {
-                self.registers.VF = 0;
-
-                let x = *self.registers.get_register_by_nibble(instruction.get_nibble(1));
-                let y = *self.registers.get_register_by_nibble(instruction.get_nibble(2));
-                let sprite_height = instruction.get_nibble(3);
-                for y_sprite_idx in (0..sprite_height).into_iter(){
-                    let sprite_byte = self.memory[self.i as usize + y_sprite_idx as usize];
-                    for x_sprite_idx in (0..8).into_iter(){
-                        let x_pos = (x.wrapping_add(x_sprite_idx)) as usize % 64;
-                        let y_pos = (y.wrapping_add(y_sprite_idx)) as usize % 32;
-                        let pixel_idx = xy_to_1d(x_pos.try_into().unwrap(), y_pos.try_into().unwrap());
-
-                        let sprite_pixel = (sprite_byte >> (7 - x_sprite_idx)) & 1;
-                        let current_pixel = self.framebuffer[pixel_idx];
-                        if sprite_pixel == 1 && current_pixel == 1{
-                            self.registers.VF = 1;
-                        }
-
-                        self.framebuffer[pixel_idx] ^= sprite_pixel;
-                    }
+            Op::Set{x, y} => {
+                let regy = self.registers.get_register_value(y);
+                self.registers.set_register_value(x, regy);
+            },
+            Op::Or{x, y} | Op::And{x, y} | Op::Xor{x, y} => {
+                let regy = self.registers.get_register_value(y);
+                let regx = self.registers.get_register_by_nibble(x);
+                match op{
+                    Op::Or{..} => *regx |= regy,
+                    Op::And{..} => *regx &= regy,
+                    _ => *regx ^= regy,
                 }
-                backend.draw_frame(&self.framebuffer);
-            },
-            0xE => {
-                let second_byte = instruction.opcode[1];
-                let key_value = self.registers.get_register_value(instruction.get_nibble(1));
-                let key = key_value.into();
-                match second_byte{
-                    0x9E => {
-                        if backend.poll_key(key){
-                            self.pc += 2;
-                        }
-                    },
-                    0xA1 => {
-                        if !backend.poll_key(key){
-                            self.pc += 2;
-                        }
-                    }
-                    _ => panic!()
+                if quirks.logic_resets_vf{
+                    self.registers.VF = 0;
+                }
+            },
+            Op::Add{x, y} => {
+                let regy = self.registers.get_register_value(y);
+                let regx = self.registers.get_register_by_nibble(x);
+                let (val, overflow) = (*regx).overflowing_add(regy);
+                *regx = val;
+                self.registers.VF = overflow as u8;
+            },
+            Op::Sub{x, y} => {
+                let regy = self.registers.get_register_value(y);
+                let regx = self.registers.get_register_by_nibble(x);
+                let carried = (*regx > regy) as u8;
+                *regx = (*regx).wrapping_sub(regy);
+                self.registers.VF = carried;
+            },
+            Op::SubReverse{x, y} => {
+                let regy = self.registers.get_register_value(y);
+                let regx = self.registers.get_register_by_nibble(x);
+                let should = (regy > *regx) as u8;
+                *regx = regy.wrapping_sub(*regx);
+                self.registers.VF = should;
+            },
+            Op::ShiftRight{x, y} => {
+                let regy = self.registers.get_register_value(y);
+                let regx = self.registers.get_register_by_nibble(x);
+                let source = if quirks.shift_uses_vy { regy } else { *regx };
+                *regx = source >> 1;
+                self.registers.VF = source & 0x1;
+            },
+            Op::ShiftLeft{x, y} => {
+                let regy = self.registers.get_register_value(y);
+                let regx = self.registers.get_register_by_nibble(x);
+                let source = if quirks.shift_uses_vy { regy } else { *regx };
+                *regx = source << 1;
+                self.registers.VF = source >> 7;
+            },
+            Op::SetIndex(address) => self.i = address,
+            Op::JumpOffset{x, address} => {
+                let offset = if quirks.jump_uses_vx{
+                    self.registers.get_register_value(x)
+                } else {
+                    self.registers.V0
+                };
+                self.pc = address + offset as u16;
+            },
+            Op::Random{x, mask} => {
+                let random_number: u8 = rand::rng().random();
+                self.registers.set_register_value(x, mask & random_number);
+            },
+            Op::Draw{x, y, height} => {
+                let x = self.registers.get_register_value(x) as usize;
+                let y = self.registers.get_register_value(y) as usize;
+                let (sprite_width, sprite_height) = match height{
+                    0 if quirks.schip_opcodes => (16, 16),
+                    height => (8, height as usize),
+                };
+                let sprite_len = sprite_width / 8 * sprite_height;
+                let planes = self.selected_planes();
+                let sprite = self.memory_range(self.i, sprite_len * planes.len())?;
+
+                let mut collision = false;
+                for (idx, plane) in planes.into_iter().enumerate(){
+                    let start = sprite.start + idx * sprite_len;
+                    let data = &self.memory[start..start + sprite_len];
+                    collision |= self.display.draw_sprite(x, y, data, sprite_width, quirks.clip_sprites, plane);
+                }
+                self.registers.VF = collision as u8;
+                self.display_dirty = true;
+            },
+            Op::SkipIfKey{x} | Op::SkipIfNotKey{x} => {
+                let key = (self.registers.get_register_value(x) & 0xF).into();
+                if backend.poll_key(key) == matches!(op, Op::SkipIfKey{..}){
+                    self.skip_next();
                 }
             },
-            0xF => {
-                let second_byte = instruction.opcode[1];
-                let register = self.registers.get_register_by_nibble(instruction.get_nibble(1));
-                match second_byte{
-                    0x07 => {
-                        *register = self.dt;
-                    },
-                    0x0A => {
-                        self.register_to_save_key = Some(instruction.get_nibble(1));
-                        backend.wait_for_key();
-                    },
-                    0x15 => {
-                        self.dt = *register;
-                    },
-                    0x18 => {
-                        self.st = *register;
-                    },
-                    0x1E => {
-                        self.i += *register as u16;
-                    },
-                    0x29 => {
-                        let val = *register;
-                        let offset = val as u16 * HEX_SPRITE_LEN;
-                        self.i = HEX_SPRITE_START + offset;
-                    },
-                    0x33 => {
-                        let mut digits = Vec::new();
-                        let mut value = *register;
-                        if value == 0{
-                            digits.push(value);
-                        } else {
-                            while value > 0{
-                                digits.push(value % 10);
-                                value /= 10;
-                            }
-                        }
-                        digits.reverse();
-                        for (idx, digit) in digits.into_iter().enumerate(){
-                            self.memory[self.i as usize + idx] = digit;
-                        }
-                    },
-                    0x55 => {
-                        for (idx, nibble) in (0..=instruction.get_nibble(1)).enumerate(){
-                            self.memory[self.i as usize + idx] = self.registers.get_register_value(nibble);
-                        }
-                    },
-                    0x65 => {
-                        let i = self.i as usize;
-                        let final_register = instruction.get_nibble(1) as usize;
-
-                        for idx in 0..=final_register{
-                            let register = self.registers.get_register_by_nibble(idx as u8);
-                            *register = self.memory[i + idx];
-                        }
+            Op::LongLoad => {
+                let address = self.memory_range(self.pc, 2)?;
+                self.i = u16::from_be_bytes([self.memory[address.start], self.memory[address.start + 1]]);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Op::SelectPlanes(planes) => self.planes = planes & 0x3,
+            Op::LoadAudio => {
+                let range = self.memory_range(self.i, 16)?;
+                self.audio_pattern = Some(self.memory[range].try_into().unwrap());
+                backend.set_audio_pattern(self.audio_pattern, self.pitch);
+            },
+            Op::GetDelay{x} => self.registers.set_register_value(x, self.dt),
+            Op::WaitKey{x} => {
+                self.register_to_save_key = Some(x);
+                backend.wait_for_key();
+            },
+            Op::SetDelay{x} => self.dt = self.registers.get_register_value(x),
+            Op::SetSound{x} => self.st = self.registers.get_register_value(x),
+            Op::AddIndex{x} => {
+                self.i = self.i.wrapping_add(self.registers.get_register_value(x) as u16);
+            },
+            Op::SmallGlyph{x} => self.i = self.small_font.glyph_address(self.registers.get_register_value(x)),
+            Op::BigGlyph{x} => self.i = self.big_font.glyph_address(self.registers.get_register_value(x)),
+            Op::Bcd{x} => {
+                let mut digits = Vec::new();
+                let mut value = self.registers.get_register_value(x);
+                if value == 0{
+                    digits.push(value);
+                } else {
+                    while value > 0{
+                        digits.push(value % 10);
+                        value /= 10;
                     }
-                    _ => panic!()
                 }
-            }
-            _ => {
-                panic!("Instruction {:?} not implemented", first_nibble)
+                digits.reverse();
+                let range = self.memory_range(self.i, digits.len())?;
+                self.memory[range].copy_from_slice(&digits);
+            },
+            Op::SetPitch{x} => {
+                self.pitch = self.registers.get_register_value(x);
+                backend.set_audio_pattern(self.audio_pattern, self.pitch);
+            },
+            Op::Store{x} => {
+                let range = self.memory_range(self.i, x as usize + 1)?;
+                for (offset, nibble) in (0..=x).enumerate(){
+                    self.memory[range.start + offset] = self.registers.get_register_value(nibble);
+                }
+                self.apply_load_store_quirk(x);
+            },
+            Op::Load{x} => {
+                let range = self.memory_range(self.i, x as usize + 1)?;
+                for (offset, nibble) in (0..=x).enumerate(){
+                    self.registers.set_register_value(nibble, self.memory[range.start + offset]);
+                }
+                self.apply_load_store_quirk(x);
+            },
+            Op::StoreFlags{x} => {
+                for nibble in 0..=x{
+                    self.rpl_flags[nibble as usize] = self.registers.get_register_value(nibble);
+                }
+            },
+            Op::LoadFlags{x} => {
+                for nibble in 0..=x{
+                    self.registers.set_register_value(nibble, self.rpl_flags[nibble as usize]);
+                }
             },
         }
+        Ok(())
+    }
+}
+
+// Registers X through Y inclusive, counting down if X > Y, as used by 5XY2 and 5XY3.
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = u8>>{
+    if x <= y{
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::backend::headless_backend::HeadlessBackend;
+    use crate::chip8::font::VIP_FONT;
+
+    // V0 = 5, V1 = 10, V0 += V1, I = 0x300, DT = V0, ST = V1, then F00A waits for a key.
+    const PROGRAM: [u8; 14] = [0x60, 0x05, 0x61, 0x0A, 0x80, 0x14, 0xA3, 0x00, 0xF0, 0x15, 0xF1, 0x18, 0xF2, 0x0A];
+
+    fn cpu_with_program() -> Cpu{
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes(&PROGRAM).unwrap();
+        cpu
+    }
+
+    // Runs every instruction of the program under the quirks.
+    fn run(quirks: Quirks, program: &[u8]) -> Cpu{
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.load_rom_bytes(program).unwrap();
+        let mut backend = HeadlessBackend::new();
+        while (cpu.pc() as usize) < PROGRAM_START + program.len(){
+            cpu.step(&mut backend).unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn step_executes_one_instruction(){
+        let mut cpu = cpu_with_program();
+        let mut backend = HeadlessBackend::new();
+        assert_eq!(cpu.step(&mut backend), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.pc(), 0x202);
+        assert_eq!(cpu.registers()[0], 5);
+        assert_eq!(cpu.registers()[1], 0);
+
+        for _ in 0..5{
+            assert_eq!(cpu.step(&mut backend), Ok(StepOutcome::Executed));
+        }
+        let mut registers = [0; 16];
+        registers[0] = 15;
+        registers[1] = 10;
+        assert_eq!(cpu.registers(), registers);
+        assert_eq!(cpu.pc(), 0x20C);
+        assert_eq!(cpu.i(), 0x300);
+        assert_eq!((cpu.dt(), cpu.st()), (15, 10));
+    }
+
+    #[test]
+    fn key_wait_stops_stepping(){
+        let mut cpu = cpu_with_program();
+        let mut backend = HeadlessBackend::new();
+        for _ in 0..6{
+            cpu.step(&mut backend).unwrap();
+        }
+        assert_eq!(cpu.step(&mut backend), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(cpu.step(&mut backend), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(cpu.pc(), 0x20E);
+
+        cpu.waiting_key_pressed(Keys::KEYW);
+        assert_eq!(cpu.registers()[2], u8::from(Keys::KEYW));
+        assert!(!cpu.is_waiting_for_key());
+    }
+
+    #[test]
+    fn tick_timers_decrements_once(){
+        let mut cpu = cpu_with_program();
+        let mut backend = HeadlessBackend::new();
+        for _ in 0..6{
+            cpu.step(&mut backend).unwrap();
+        }
+        // Stepping never touches the timers.
+        assert_eq!((cpu.dt(), cpu.st()), (15, 10));
+        cpu.tick_timers();
+        assert_eq!((cpu.dt(), cpu.st()), (14, 9));
+        for _ in 0..20{
+            cpu.tick_timers();
+        }
+        assert_eq!((cpu.dt(), cpu.st()), (0, 0));
+        assert!(!cpu.sound_active());
+    }
+
+    #[test]
+    fn same_program_same_state(){
+        let mut backend = HeadlessBackend::new();
+        let mut first = cpu_with_program();
+        let mut second = cpu_with_program();
+        for _ in 0..4{
+            first.step(&mut backend).unwrap();
+            first.tick_timers();
+        }
+        for _ in 0..4{
+            second.step(&mut backend).unwrap();
+            second.tick_timers();
+        }
+        assert!(first.snapshot() == second.snapshot());
+    }
+
+    #[test]
+    fn unknown_opcode_faults(){
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes(&[0x81, 0x28]).unwrap();
+        let fault = cpu.step(&mut HeadlessBackend::new()).unwrap_err();
+        assert_eq!(fault, CpuFault{pc: 0x200, opcode: 0x8128, kind: FaultKind::UnknownOpcode});
+    }
+
+    #[test]
+    fn schip_opcodes_need_the_quirk(){
+        for opcode in [0x00C2u16, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF, 0xF030, 0xF075, 0xF085]{
+            let mut cpu = Cpu::with_quirks(Quirks::cosmac_vip());
+            cpu.load_rom_bytes(&opcode.to_be_bytes()).unwrap();
+            let fault = cpu.step(&mut HeadlessBackend::new()).unwrap_err();
+            assert_eq!(fault.kind, FaultKind::UnknownOpcode, "{opcode:04X}");
+
+            let mut cpu = Cpu::with_quirks(Quirks::superchip());
+            cpu.load_rom_bytes(&opcode.to_be_bytes()).unwrap();
+            assert!(cpu.step(&mut HeadlessBackend::new()).is_ok(), "{opcode:04X}");
+        }
+    }
+
+    #[test]
+    fn big_sprites_need_the_quirk(){
+        // I = 0x206, the sprite below, then D000.
+        let program = [0xA2, 0x06, 0xD0, 0x00, 0x00, 0x00, 0xFF, 0xFF];
+        let mut backend = HeadlessBackend::new();
+        let mut vip = Cpu::with_quirks(Quirks::cosmac_vip());
+        vip.load_rom_bytes(&program).unwrap();
+        vip.step(&mut backend).unwrap();
+        vip.step(&mut backend).unwrap();
+        assert!(vip.display().pixels().iter().all(|&pixel| pixel == 0));
+
+        let mut schip = Cpu::with_quirks(Quirks::superchip());
+        schip.load_rom_bytes(&program).unwrap();
+        schip.step(&mut backend).unwrap();
+        schip.step(&mut backend).unwrap();
+        assert_eq!(schip.display().pixels().iter().filter(|&&pixel| pixel != 0).count(), 16);
+    }
+
+    #[test]
+    fn xo_chip_opcodes_need_the_quirk(){
+        for opcode in [0xF000u16, 0xF101, 0x5012, 0x5013, 0xF002, 0xF03A, 0x00D1]{
+            let mut cpu = Cpu::with_quirks(Quirks::modern());
+            cpu.load_rom_bytes(&[opcode.to_be_bytes(), [0x03, 0x00]].concat()).unwrap();
+            let fault = cpu.step(&mut HeadlessBackend::new()).unwrap_err();
+            assert_eq!(fault.kind, FaultKind::UnknownOpcode, "{opcode:04X}");
+
+            let mut cpu = Cpu::with_quirks(Quirks::xochip());
+            cpu.load_rom_bytes(&[opcode.to_be_bytes(), [0x03, 0x00]].concat()).unwrap();
+            assert!(cpu.step(&mut HeadlessBackend::new()).is_ok(), "{opcode:04X}");
+        }
+    }
+
+    #[test]
+    fn skipping_a_long_load(){
+        // 3000 skips because V0 is 0, over an F000 NNNN.
+        let program = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34];
+        let mut backend = HeadlessBackend::new();
+        let mut xo = Cpu::with_quirks(Quirks::xochip());
+        xo.load_rom_bytes(&program).unwrap();
+        xo.step(&mut backend).unwrap();
+        assert_eq!(xo.pc(), 0x206);
+
+        let mut modern = Cpu::with_quirks(Quirks::modern());
+        modern.load_rom_bytes(&program).unwrap();
+        modern.step(&mut backend).unwrap();
+        assert_eq!(modern.pc(), 0x204);
+    }
+
+    #[test]
+    fn shift_source_quirk(){
+        // V0 = 5, V1 = 6, then V0 = V1 >> 1 or V0 >> 1.
+        let program = [0x60, 0x05, 0x61, 0x06, 0x80, 0x16];
+        let vip = run(Quirks::cosmac_vip(), &program);
+        assert_eq!((vip.registers()[0], vip.registers()[0xF]), (3, 0));
+        let modern = run(Quirks::modern(), &program);
+        assert_eq!((modern.registers()[0], modern.registers()[0xF]), (2, 1));
+
+        // V0 = 5, V1 = 0x81, then V0 = V1 << 1 or V0 << 1.
+        let program = [0x60, 0x05, 0x61, 0x81, 0x80, 0x1E];
+        let vip = run(Quirks::cosmac_vip(), &program);
+        assert_eq!((vip.registers()[0], vip.registers()[0xF]), (2, 1));
+        let modern = run(Quirks::modern(), &program);
+        assert_eq!((modern.registers()[0], modern.registers()[0xF]), (10, 0));
+    }
+
+    #[test]
+    fn load_store_quirk(){
+        // I = 0x300, store V0-V2, then load V0-V2.
+        for last in [0x55, 0x65]{
+            let program = [0xA3, 0x00, 0xF2, last];
+            assert_eq!(run(Quirks::cosmac_vip(), &program).i(), 0x303);
+            assert_eq!(run(Quirks::chip48(), &program).i(), 0x302);
+            assert_eq!(run(Quirks::modern(), &program).i(), 0x300);
+        }
+    }
+
+    #[test]
+    fn jump_offset_quirk(){
+        // V0 = 0x10, V1 = 0x20, then B120 jumps to 0x120 plus V0 or V1.
+        let program = [0x60, 0x10, 0x61, 0x20, 0xB1, 0x20];
+        let mut backend = HeadlessBackend::new();
+        for (quirks, target) in [(Quirks::cosmac_vip(), 0x130), (Quirks::superchip(), 0x140)]{
+            let mut cpu = Cpu::with_quirks(quirks);
+            cpu.load_rom_bytes(&program).unwrap();
+            for _ in 0..3{
+                cpu.step(&mut backend).unwrap();
+            }
+            assert_eq!(cpu.pc(), target);
+        }
+    }
+
+    #[test]
+    fn logic_resets_vf_quirk(){
+        for op in [0x01, 0x02, 0x03]{
+            // VF = 5, V0 = 3, V1 = 6, then OR, AND or XOR.
+            let program = [0x6F, 0x05, 0x60, 0x03, 0x61, 0x06, 0x80, 0x10 | op];
+            assert_eq!(run(Quirks::cosmac_vip(), &program).registers()[0xF], 0);
+            assert_eq!(run(Quirks::modern(), &program).registers()[0xF], 5);
+        }
+    }
+
+    #[test]
+    fn clip_sprites_quirk(){
+        // A row of 8 pixels drawn at x = 62, so 6 of them fall off the right edge.
+        let program = [0x60, 0x3E, 0x61, 0x00, 0xA2, 0x08, 0xD0, 0x11, 0xFF];
+        let lit = |cpu: &Cpu| cpu.display().pixels()[..64].iter().filter(|&&pixel| pixel != 0).count();
+        let mut backend = HeadlessBackend::new();
+        for (quirks, expected) in [(Quirks::cosmac_vip(), 2), (Quirks::modern(), 8)]{
+            let mut cpu = Cpu::with_quirks(quirks);
+            cpu.load_rom_bytes(&program).unwrap();
+            for _ in 0..4{
+                cpu.step(&mut backend).unwrap();
+            }
+            assert_eq!(lit(&cpu), expected);
+        }
+    }
+
+    #[test]
+    fn glyphs_follow_a_moved_font(){
+        let mut cpu = Cpu::with_quirks(Quirks::superchip());
+        cpu.load_font(&VIP_FONT, 0x100).unwrap();
+        cpu.load_big_font(&SCHIP_BIG_FONT, 0x150).unwrap();
+        // V0 = 0xA, then FX29 and FX30.
+        cpu.load_rom_bytes(&[0x60, 0x0A, 0xF0, 0x29, 0xF0, 0x30]).unwrap();
+        let mut backend = HeadlessBackend::new();
+        cpu.step(&mut backend).unwrap();
+
+        cpu.step(&mut backend).unwrap();
+        assert_eq!(cpu.i(), 0x100 + 0xA * 5);
+        assert_eq!(&cpu.memory()[cpu.i() as usize..][..5], &VIP_FONT.data[0xA * 5..][..5]);
+
+        cpu.step(&mut backend).unwrap();
+        assert_eq!(cpu.i(), 0x150 + 0xA * 10);
+        assert_eq!(&cpu.memory()[cpu.i() as usize..][..10], &SCHIP_BIG_FONT.data[0xA * 10..][..10]);
+    }
+
+    #[test]
+    fn display_changes_mark_it_dirty(){
+        // V0 = 1, I = sprite, clear, scroll right, draw, then the sprite byte.
+        let program = [0x60, 0x01, 0xA2, 0x0A, 0x00, 0xE0, 0x00, 0xFB, 0xD0, 0x01, 0x80];
+        let mut cpu = Cpu::with_quirks(Quirks::superchip());
+        cpu.load_rom_bytes(&program).unwrap();
+        let mut backend = HeadlessBackend::new();
+        assert!(cpu.present_if_dirty(&mut backend));
+        assert!(!cpu.is_display_dirty());
+        for _ in 0..2{
+            cpu.step(&mut backend).unwrap();
+            assert!(!cpu.is_display_dirty());
+        }
+        // Clear, scroll and draw each leave a frame to present.
+        for _ in 0..3{
+            cpu.step(&mut backend).unwrap();
+            assert!(cpu.is_display_dirty(), "{:03X}", cpu.pc());
+            assert!(cpu.present_if_dirty(&mut backend));
+            assert!(!cpu.is_display_dirty());
+        }
+        assert_eq!(backend.frames_drawn, 4);
+        assert!(backend.pixel(1, 1));
+    }
+
+    #[test]
+    fn unchanged_frames_are_presented_once(){
+        let mut cpu = cpu_with_program();
+        let mut backend = HeadlessBackend::new();
+        assert!(cpu.present_if_dirty(&mut backend));
+        assert!(!cpu.present_if_dirty(&mut backend));
+        cpu.step(&mut backend).unwrap();
+        assert!(!cpu.present_if_dirty(&mut backend));
+        assert_eq!(backend.frames_drawn, 1);
     }
 }
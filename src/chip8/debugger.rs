@@ -0,0 +1,566 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::backend::backend::Backend;
+use crate::chip8::cpu::{Cpu, StepOutcome};
+use crate::chip8::error::CpuFault;
+use crate::chip8::op::{Instruction, Op};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind{
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess{
+    pub kind: AccessKind,
+    pub range: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind{
+    Read,
+    Write,
+    ReadWrite,
+}
+
+// Watches the inclusive address range start..=end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint{
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint{
+    // The first watched address touched by an access, if any.
+    fn hit(&self, access: &MemoryAccess) -> Option<u16>{
+        let kind_matches = match self.kind{
+            WatchKind::Read => access.kind == AccessKind::Read,
+            WatchKind::Write => access.kind == AccessKind::Write,
+            WatchKind::ReadWrite => true,
+        };
+        let first = access.range.start.max(self.start as usize);
+        let last = access.range.end.min(self.end as usize + 1);
+        (kind_matches && first < last).then_some(first as u16)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target{
+    V(u8),
+    I,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison{
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// A register condition such as `v3 == 0x10` or `i >= 0x300`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition{
+    pub target: Target,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition{
+    pub fn holds(&self, cpu: &Cpu) -> bool{
+        let current = match self.target{
+            Target::V(register) => cpu.registers()[register as usize] as u16,
+            Target::I => cpu.i(),
+            Target::Dt => cpu.dt() as u16,
+            Target::St => cpu.st() as u16,
+        };
+        match self.comparison{
+            Comparison::Eq => current == self.value,
+            Comparison::Ne => current != self.value,
+            Comparison::Lt => current < self.value,
+            Comparison::Le => current <= self.value,
+            Comparison::Gt => current > self.value,
+            Comparison::Ge => current >= self.value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason{
+    Stepped,
+    Breakpoint(u16),
+    Watchpoint{
+        pc: u16,
+        address: u16,
+        kind: AccessKind,
+    },
+    Condition(usize),
+    // A jump to itself, the usual way a CHIP-8 program ends.
+    Spinning(u16),
+    WaitingForKey,
+    Exited,
+    StepLimit,
+    NotInSubroutine,
+    Fault(CpuFault),
+}
+
+// Runs a Cpu one instruction at a time, stopping on breakpoints, watchpoints
+// and register conditions. Timers are ticked every `instructions_per_frame`
+// instructions so timing loops behave as they would at full speed.
+pub struct Debugger{
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    conditions: Vec<(Condition, bool)>,
+    instructions_per_frame: u32,
+    frame_progress: u32,
+    pub executed: u64,
+}
+
+impl Debugger{
+    pub fn new(instructions_per_frame: u32) -> Self{
+        Self{
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            instructions_per_frame: instructions_per_frame.max(1),
+            frame_progress: 0,
+            executed: 0,
+        }
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_{
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) -> bool{
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool{
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint]{
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint){
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = &Condition>{
+        self.conditions.iter().map(|(condition, _)| condition)
+    }
+
+    // Conditions only trigger when they go from false to true, so one that
+    // already holds when added waits for the next time it becomes true.
+    pub fn add_condition(&mut self, condition: Condition, cpu: &Cpu){
+        self.conditions.push((condition, condition.holds(cpu)));
+    }
+
+    pub fn clear(&mut self){
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.conditions.clear();
+    }
+
+    pub fn step_into<B: Backend>(&mut self, cpu: &mut Cpu, backend: &mut B) -> StopReason{
+        self.run_until(cpu, backend, Some(1), |_| true)
+    }
+
+    // Runs a whole 2NNN call as one step; anything else is a plain step.
+    pub fn step_over<B: Backend>(&mut self, cpu: &mut Cpu, backend: &mut B, limit: Option<u64>) -> StopReason{
+        let op = next_opcode(cpu).map(|opcode| Instruction::from(opcode).decode());
+        if let Some(Ok(Op::Call(_))) = op{
+            let depth = cpu.stack().len();
+            self.run_until(cpu, backend, limit, |cpu| cpu.stack().len() <= depth)
+        } else {
+            self.step_into(cpu, backend)
+        }
+    }
+
+    pub fn step_out<B: Backend>(&mut self, cpu: &mut Cpu, backend: &mut B, limit: Option<u64>) -> StopReason{
+        let depth = cpu.stack().len();
+        if depth == 0{
+            return StopReason::NotInSubroutine;
+        }
+        self.run_until(cpu, backend, limit, |cpu| cpu.stack().len() < depth)
+    }
+
+    pub fn resume<B: Backend>(&mut self, cpu: &mut Cpu, backend: &mut B, limit: Option<u64>) -> StopReason{
+        self.run_until(cpu, backend, limit, |_| false)
+    }
+
+    fn run_until<B, F>(&mut self, cpu: &mut Cpu, backend: &mut B, limit: Option<u64>, done: F) -> StopReason
+    where
+        B: Backend,
+        F: Fn(&Cpu) -> bool,
+    {
+        let mut steps = 0;
+        loop{
+            let pc = cpu.pc();
+            // The first instruction always runs, so continuing from a breakpoint
+            // doesn't stop on it again straight away.
+            if steps > 0 && self.breakpoints.contains(&pc){
+                return StopReason::Breakpoint(pc);
+            }
+            if limit.is_some_and(|limit| steps >= limit){
+                return StopReason::StepLimit;
+            }
+            if cpu.has_exited(){
+                return StopReason::Exited;
+            }
+            if cpu.is_waiting_for_key(){
+                return StopReason::WaitingForKey;
+            }
+
+            let access = pending_access(cpu);
+            let outcome = match cpu.step(backend){
+                Ok(outcome) => outcome,
+                Err(fault) => return StopReason::Fault(fault),
+            };
+            steps += 1;
+            self.executed += 1;
+            self.advance_frame(cpu, backend);
+
+            let watched = access.as_ref().and_then(|access| {
+                self.watchpoints.iter().find_map(|watchpoint| watchpoint.hit(access)).map(|address| (address, access.kind))
+            });
+            if let Some((address, kind)) = watched{
+                return StopReason::Watchpoint{pc, address, kind};
+            }
+            if let Some(index) = self.update_conditions(cpu){
+                return StopReason::Condition(index);
+            }
+
+            match outcome{
+                StepOutcome::Exited => return StopReason::Exited,
+                StepOutcome::WaitingForKey => return StopReason::WaitingForKey,
+                _ if done(cpu) => return StopReason::Stepped,
+                StepOutcome::Executed if cpu.pc() == pc => return StopReason::Spinning(pc),
+                _ => (),
+            }
+        }
+    }
+
+    fn advance_frame<B: Backend>(&mut self, cpu: &mut Cpu, backend: &mut B){
+        self.frame_progress += 1;
+        if self.frame_progress >= self.instructions_per_frame{
+            self.frame_progress = 0;
+            cpu.tick_timers();
+            backend.set_beep(cpu.sound_active());
+        }
+    }
+
+    // Re-evaluates every condition and returns the first one that just became true.
+    fn update_conditions(&mut self, cpu: &Cpu) -> Option<usize>{
+        let mut triggered = None;
+        for (index, (condition, held)) in self.conditions.iter_mut().enumerate(){
+            let holds = condition.holds(cpu);
+            if holds && !*held && triggered.is_none(){
+                triggered = Some(index);
+            }
+            *held = holds;
+        }
+        triggered
+    }
+}
+
+pub fn next_opcode(cpu: &Cpu) -> Option<u16>{
+    let pc = cpu.pc() as usize;
+    cpu.memory().get(pc..pc + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// The data memory the instruction at PC is about to read or write. Instruction
+// fetches, including the second word of F000 NNNN, are not counted.
+pub fn pending_access(cpu: &Cpu) -> Option<MemoryAccess>{
+    let op = Instruction::from(next_opcode(cpu)?).decode().ok()?;
+    let i = cpu.i() as usize;
+
+    let (kind, len) = match op{
+        Op::SaveRange{x, y} => (AccessKind::Write, x.abs_diff(y) as usize + 1),
+        Op::LoadRange{x, y} => (AccessKind::Read, x.abs_diff(y) as usize + 1),
+        Op::Draw{height, ..} => {
+            // DXY0 only draws a 16x16 sprite with SCHIP opcodes, otherwise nothing.
+            let sprite_len = match height{
+                0 if cpu.quirks().schip_opcodes => 32,
+                height => height as usize,
+            };
+            (AccessKind::Read, sprite_len * cpu.planes().count_ones() as usize)
+        },
+        Op::LoadAudio => (AccessKind::Read, 16),
+        Op::Bcd{x} => {
+            let digits = match cpu.registers()[x as usize]{
+                0..=9 => 1,
+                10..=99 => 2,
+                _ => 3,
+            };
+            (AccessKind::Write, digits)
+        },
+        Op::Store{x} => (AccessKind::Write, x as usize + 1),
+        Op::Load{x} => (AccessKind::Read, x as usize + 1),
+        _ => return None,
+    };
+    Some(MemoryAccess{kind, range: i..i + len})
+}
+
+impl FromStr for Target{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        let lower = s.to_ascii_lowercase();
+        match lower.as_str(){
+            "i" => Ok(Target::I),
+            "dt" => Ok(Target::Dt),
+            "st" => Ok(Target::St),
+            _ => lower.strip_prefix('v')
+                .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                .filter(|&register| register < 16)
+                .map(Target::V)
+                .ok_or_else(|| format!("unknown register `{s}`, expected v0-vf, i, dt or st")),
+        }
+    }
+}
+
+impl FromStr for Comparison{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        match s{
+            "==" => Ok(Comparison::Eq),
+            "!=" => Ok(Comparison::Ne),
+            "<" => Ok(Comparison::Lt),
+            "<=" => Ok(Comparison::Le),
+            ">" => Ok(Comparison::Gt),
+            ">=" => Ok(Comparison::Ge),
+            _ => Err(format!("unknown comparison `{s}`, expected ==, !=, <, <=, > or >=")),
+        }
+    }
+}
+
+impl fmt::Display for Target{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            Target::V(register) => write!(f, "v{register:X}"),
+            Target::I => write!(f, "i"),
+            Target::Dt => write!(f, "dt"),
+            Target::St => write!(f, "st"),
+        }
+    }
+}
+
+impl fmt::Display for Comparison{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        let symbol = match self{
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+impl fmt::Display for Condition{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "{} {} {:#X}", self.target, self.comparison, self.value)
+    }
+}
+
+impl fmt::Display for Watchpoint{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        let kind = match self.kind{
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::ReadWrite => "read/write",
+        };
+        if self.start == self.end{
+            write!(f, "{kind} {:#05X}", self.start)
+        } else {
+            write!(f, "{kind} {:#05X}-{:#05X}", self.start, self.end)
+        }
+    }
+}
+
+impl fmt::Display for StopReason{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            StopReason::Stepped => write!(f, "stepped"),
+            StopReason::Breakpoint(address) => write!(f, "breakpoint at {address:#05X}"),
+            StopReason::Watchpoint{pc, address, kind} => {
+                let access = match kind{
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "write",
+                };
+                write!(f, "watchpoint: {access} of {address:#05X} by the instruction at {pc:#05X}")
+            },
+            StopReason::Condition(index) => write!(f, "condition {index} became true"),
+            StopReason::Spinning(address) => write!(f, "program is spinning on a jump to itself at {address:#05X}"),
+            StopReason::WaitingForKey => write!(f, "waiting for a key press"),
+            StopReason::Exited => write!(f, "program exited"),
+            StopReason::StepLimit => write!(f, "step limit reached"),
+            StopReason::NotInSubroutine => write!(f, "not inside a subroutine"),
+            StopReason::Fault(fault) => write!(f, "halted: {fault}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::backend::headless_backend::HeadlessBackend;
+    use crate::chip8::quirks::Quirks;
+
+    // V0 += 1 forever.
+    const COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+    // I = 0x300, store V0-V2, load V0-V1, then spin.
+    const MEMORY: [u8; 8] = [0xA3, 0x00, 0xF2, 0x55, 0xF1, 0x65, 0x12, 0x06];
+    // Calls the subroutine at 0x206, which sets V1 = 1 and returns.
+    const CALL: [u8; 10] = [0x22, 0x06, 0x60, 0x05, 0x12, 0x04, 0x61, 0x01, 0x00, 0xEE];
+
+    fn cpu_with(quirks: Quirks, program: &[u8]) -> Cpu{
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.load_rom_bytes(program).unwrap();
+        cpu
+    }
+
+    fn watch(start: u16, end: u16, kind: WatchKind) -> Debugger{
+        let mut debugger = Debugger::new(11);
+        debugger.add_watchpoint(Watchpoint{start, end, kind});
+        debugger
+    }
+
+    #[test]
+    fn breakpoints(){
+        let mut cpu = cpu_with(Quirks::modern(), &COUNTER);
+        let mut backend = HeadlessBackend::new();
+        let mut debugger = Debugger::new(11);
+        assert!(debugger.add_breakpoint(0x202));
+        assert_eq!(debugger.resume(&mut cpu, &mut backend, None), StopReason::Breakpoint(0x202));
+        assert_eq!(cpu.registers()[0], 1);
+        // Resuming from a breakpoint runs past it before stopping there again.
+        assert_eq!(debugger.resume(&mut cpu, &mut backend, None), StopReason::Breakpoint(0x202));
+        assert_eq!(cpu.registers()[0], 2);
+
+        assert!(debugger.remove_breakpoint(0x202));
+        assert_eq!(debugger.resume(&mut cpu, &mut backend, Some(10)), StopReason::StepLimit);
+        assert_eq!(debugger.executed, 13);
+    }
+
+    #[test]
+    fn write_watchpoint(){
+        let mut cpu = cpu_with(Quirks::modern(), &MEMORY);
+        let reason = watch(0x302, 0x302, WatchKind::Write).resume(&mut cpu, &mut HeadlessBackend::new(), None);
+        assert_eq!(reason, StopReason::Watchpoint{pc: 0x202, address: 0x302, kind: AccessKind::Write});
+    }
+
+    #[test]
+    fn read_watchpoint(){
+        let mut cpu = cpu_with(Quirks::modern(), &MEMORY);
+        let reason = watch(0x300, 0x301, WatchKind::Read).resume(&mut cpu, &mut HeadlessBackend::new(), None);
+        assert_eq!(reason, StopReason::Watchpoint{pc: 0x204, address: 0x300, kind: AccessKind::Read});
+    }
+
+    #[test]
+    fn read_write_watchpoint(){
+        let mut cpu = cpu_with(Quirks::modern(), &MEMORY);
+        let mut backend = HeadlessBackend::new();
+        let mut debugger = watch(0x301, 0x301, WatchKind::ReadWrite);
+        let reason = debugger.resume(&mut cpu, &mut backend, None);
+        assert_eq!(reason, StopReason::Watchpoint{pc: 0x202, address: 0x301, kind: AccessKind::Write});
+        let reason = debugger.resume(&mut cpu, &mut backend, None);
+        assert_eq!(reason, StopReason::Watchpoint{pc: 0x204, address: 0x301, kind: AccessKind::Read});
+        assert_eq!(debugger.resume(&mut cpu, &mut backend, None), StopReason::Spinning(0x206));
+    }
+
+    #[test]
+    fn big_sprite_reads_follow_the_quirks(){
+        // I = 0x300, D000, then spin.
+        let program = [0xA3, 0x00, 0xD0, 0x00, 0x12, 0x04];
+        let mut backend = HeadlessBackend::new();
+
+        let mut cpu = cpu_with(Quirks::superchip(), &program);
+        let reason = watch(0x31F, 0x31F, WatchKind::Read).resume(&mut cpu, &mut backend, None);
+        assert_eq!(reason, StopReason::Watchpoint{pc: 0x202, address: 0x31F, kind: AccessKind::Read});
+
+        let mut cpu = cpu_with(Quirks::cosmac_vip(), &program);
+        let reason = watch(0x300, 0x31F, WatchKind::Read).resume(&mut cpu, &mut backend, None);
+        assert_eq!(reason, StopReason::Spinning(0x204));
+    }
+
+    #[test]
+    fn conditions_trigger_on_the_edge(){
+        let mut cpu = cpu_with(Quirks::modern(), &COUNTER);
+        let mut backend = HeadlessBackend::new();
+        let mut debugger = Debugger::new(11);
+        debugger.add_condition(Condition{target: Target::V(0), comparison: Comparison::Eq, value: 3}, &cpu);
+        assert_eq!(debugger.resume(&mut cpu, &mut backend, None), StopReason::Condition(0));
+        assert_eq!(cpu.registers()[0], 3);
+        // Still true after the jump, false after the next add, and only true
+        // again once V0 wraps around.
+        assert_eq!(debugger.resume(&mut cpu, &mut backend, Some(100)), StopReason::StepLimit);
+
+        // A condition that already holds waits until it stops holding.
+        let mut debugger = Debugger::new(11);
+        debugger.add_condition(Condition{target: Target::V(0), comparison: Comparison::Ge, value: 0}, &cpu);
+        assert_eq!(debugger.resume(&mut cpu, &mut backend, Some(100)), StopReason::StepLimit);
+    }
+
+    #[test]
+    fn step_over_runs_a_whole_call(){
+        let mut cpu = cpu_with(Quirks::modern(), &CALL);
+        let mut backend = HeadlessBackend::new();
+        let mut debugger = Debugger::new(11);
+        assert_eq!(debugger.step_over(&mut cpu, &mut backend, None), StopReason::Stepped);
+        assert_eq!((cpu.pc(), cpu.registers()[1]), (0x202, 1));
+        assert_eq!(debugger.executed, 3);
+        // Anything but a call is a single step.
+        assert_eq!(debugger.step_over(&mut cpu, &mut backend, None), StopReason::Stepped);
+        assert_eq!(cpu.pc(), 0x204);
+    }
+
+    #[test]
+    fn step_over_stops_inside_a_call(){
+        let mut cpu = cpu_with(Quirks::modern(), &CALL);
+        let mut debugger = Debugger::new(11);
+        debugger.add_breakpoint(0x208);
+        assert_eq!(debugger.step_over(&mut cpu, &mut HeadlessBackend::new(), None), StopReason::Breakpoint(0x208));
+    }
+
+    #[test]
+    fn step_out_returns_to_the_caller(){
+        let mut cpu = cpu_with(Quirks::modern(), &CALL);
+        let mut backend = HeadlessBackend::new();
+        let mut debugger = Debugger::new(11);
+        assert_eq!(debugger.step_out(&mut cpu, &mut backend, None), StopReason::NotInSubroutine);
+        assert_eq!(debugger.step_into(&mut cpu, &mut backend), StopReason::Stepped);
+        assert_eq!(cpu.pc(), 0x206);
+
+        assert_eq!(debugger.step_out(&mut cpu, &mut backend, Some(1)), StopReason::StepLimit);
+        assert_eq!(cpu.pc(), 0x208);
+        assert_eq!(debugger.step_out(&mut cpu, &mut backend, None), StopReason::Stepped);
+        assert_eq!(cpu.pc(), 0x202);
+    }
+
+    #[test]
+    fn timers_tick_every_frame_of_instructions(){
+        // DT = V0 = 10, then count.
+        let mut program = vec![0x60, 0x0A, 0xF0, 0x15];
+        program.extend([0x71, 0x01, 0x12, 0x04]);
+        let mut cpu = cpu_with(Quirks::modern(), &program);
+        let mut debugger = Debugger::new(4);
+        debugger.resume(&mut cpu, &mut HeadlessBackend::new(), Some(10));
+        assert_eq!(cpu.dt(), 8);
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::error::InvalidOpcode;
+use crate::chip8::op::{Instruction, Op};
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax{
+    Octo,
+    Cowgod,
+}
+
+// Where control can go after an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow{
+    Next,
+    // May skip the following instruction.
+    Skip,
+    Jump(u16),
+    // BNNN, the target depends on a register at run time.
+    JumpOffset(u16),
+    Call(u16),
+    Return,
+    Exit,
+    // F000 NNNN, followed by a 2-byte address operand.
+    LongLoad,
+    Invalid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedInstruction{
+    pub opcode: u16,
+    pub op: Result<Op, InvalidOpcode>,
+}
+
+pub fn disassemble(opcode: u16) -> DecodedInstruction{
+    DecodedInstruction{
+        opcode,
+        op: Instruction::from(opcode).decode(),
+    }
+}
+
+impl DecodedInstruction{
+    // Size in bytes, including the operand of a long load.
+    pub fn size(&self) -> u16{
+        self.op.map_or(2, |op| op.size())
+    }
+
+    pub fn is_valid(&self) -> bool{
+        self.op.is_ok()
+    }
+
+    pub fn flow(&self) -> Flow{
+        match self.op{
+            Err(_) => Flow::Invalid,
+            Ok(Op::Return) => Flow::Return,
+            Ok(Op::Exit) => Flow::Exit,
+            Ok(Op::Jump(address)) => Flow::Jump(address),
+            Ok(Op::JumpOffset{address, ..}) => Flow::JumpOffset(address),
+            Ok(Op::Call(address)) => Flow::Call(address),
+            Ok(Op::LongLoad) => Flow::LongLoad,
+            Ok(op) if op.is_skip() => Flow::Skip,
+            Ok(_) => Flow::Next,
+        }
+    }
+
+    // The address an ANNN instruction points I at, usually sprite data.
+    pub fn index_target(&self) -> Option<u16>{
+        match self.op{
+            Ok(Op::SetIndex(address)) => Some(address),
+            _ => None,
+        }
+    }
+
+    // Formats the instruction, substituting `label` for its address operand.
+    pub fn formatted<'a>(&'a self, syntax: Syntax, label: Option<&'a str>) -> Formatted<'a>{
+        Formatted{instruction: self, syntax, label}
+    }
+
+    fn fmt_octo(&self, f: &mut fmt::Formatter<'_>, address: &str) -> fmt::Result{
+        let Ok(op) = self.op else {
+            return write!(f, "{:#04X} {:#04X}", self.opcode >> 8, self.opcode & 0xFF);
+        };
+        match op{
+            Op::Clear => write!(f, "clear"),
+            Op::Return => write!(f, "return"),
+            Op::ScrollDown(rows) => write!(f, "scroll-down {rows}"),
+            Op::ScrollUp(rows) => write!(f, "scroll-up {rows}"),
+            Op::ScrollRight => write!(f, "scroll-right"),
+            Op::ScrollLeft => write!(f, "scroll-left"),
+            Op::Exit => write!(f, "exit"),
+            Op::Lores => write!(f, "lores"),
+            Op::Hires => write!(f, "hires"),
+            Op::Jump(_) => write!(f, "jump {address}"),
+            Op::Call(_) => write!(f, ":call {address}"),
+            Op::SkipIfEqualByte{x, byte} => write!(f, "if v{x:x} != {byte:#04X} then"),
+            Op::SkipIfNotEqualByte{x, byte} => write!(f, "if v{x:x} == {byte:#04X} then"),
+            Op::SkipIfEqual{x, y} => write!(f, "if v{x:x} != v{y:x} then"),
+            Op::SaveRange{x, y} => write!(f, "save v{x:x} - v{y:x}"),
+            Op::LoadRange{x, y} => write!(f, "load v{x:x} - v{y:x}"),
+            Op::SetByte{x, byte} => write!(f, "v{x:x} := {byte:#04X}"),
+            Op::AddByte{x, byte} => write!(f, "v{x:x} += {byte:#04X}"),
+            Op::Set{x, y} => write!(f, "v{x:x} := v{y:x}"),
+            Op::Or{x, y} => write!(f, "v{x:x} |= v{y:x}"),
+            Op::And{x, y} => write!(f, "v{x:x} &= v{y:x}"),
+            Op::Xor{x, y} => write!(f, "v{x:x} ^= v{y:x}"),
+            Op::Add{x, y} => write!(f, "v{x:x} += v{y:x}"),
+            Op::Sub{x, y} => write!(f, "v{x:x} -= v{y:x}"),
+            Op::ShiftRight{x, y} => write!(f, "v{x:x} >>= v{y:x}"),
+            Op::SubReverse{x, y} => write!(f, "v{x:x} =- v{y:x}"),
+            Op::ShiftLeft{x, y} => write!(f, "v{x:x} <<= v{y:x}"),
+            Op::SkipIfNotEqual{x, y} => write!(f, "if v{x:x} == v{y:x} then"),
+            Op::SetIndex(_) => write!(f, "i := {address}"),
+            Op::JumpOffset{..} => write!(f, "jump0 {address}"),
+            Op::Random{x, mask} => write!(f, "v{x:x} := random {mask:#04X}"),
+            Op::Draw{x, y, height} => write!(f, "sprite v{x:x} v{y:x} {height}"),
+            Op::SkipIfKey{x} => write!(f, "if v{x:x} -key then"),
+            Op::SkipIfNotKey{x} => write!(f, "if v{x:x} key then"),
+            Op::LongLoad => write!(f, "i := long"),
+            Op::SelectPlanes(planes) => write!(f, "plane {planes}"),
+            Op::LoadAudio => write!(f, "audio"),
+            Op::GetDelay{x} => write!(f, "v{x:x} := delay"),
+            Op::WaitKey{x} => write!(f, "v{x:x} := key"),
+            Op::SetDelay{x} => write!(f, "delay := v{x:x}"),
+            Op::SetSound{x} => write!(f, "buzzer := v{x:x}"),
+            Op::AddIndex{x} => write!(f, "i += v{x:x}"),
+            Op::SmallGlyph{x} => write!(f, "i := hex v{x:x}"),
+            Op::BigGlyph{x} => write!(f, "i := bighex v{x:x}"),
+            Op::Bcd{x} => write!(f, "bcd v{x:x}"),
+            Op::SetPitch{x} => write!(f, "pitch := v{x:x}"),
+            Op::Store{x} => write!(f, "save v{x:x}"),
+            Op::Load{x} => write!(f, "load v{x:x}"),
+            Op::StoreFlags{x} => write!(f, "saveflags v{x:x}"),
+            Op::LoadFlags{x} => write!(f, "loadflags v{x:x}"),
+        }
+    }
+
+    fn fmt_cowgod(&self, f: &mut fmt::Formatter<'_>, address: &str) -> fmt::Result{
+        let Ok(op) = self.op else {
+            return write!(f, "DW {:#06X}", self.opcode);
+        };
+        match op{
+            Op::Clear => write!(f, "CLS"),
+            Op::Return => write!(f, "RET"),
+            Op::ScrollDown(rows) => write!(f, "SCD {rows}"),
+            Op::ScrollUp(rows) => write!(f, "SCU {rows}"),
+            Op::ScrollRight => write!(f, "SCR"),
+            Op::ScrollLeft => write!(f, "SCL"),
+            Op::Exit => write!(f, "EXIT"),
+            Op::Lores => write!(f, "LOW"),
+            Op::Hires => write!(f, "HIGH"),
+            Op::Jump(_) => write!(f, "JP {address}"),
+            Op::Call(_) => write!(f, "CALL {address}"),
+            Op::SkipIfEqualByte{x, byte} => write!(f, "SE V{x:X}, {byte:#04X}"),
+            Op::SkipIfNotEqualByte{x, byte} => write!(f, "SNE V{x:X}, {byte:#04X}"),
+            Op::SkipIfEqual{x, y} => write!(f, "SE V{x:X}, V{y:X}"),
+            Op::SaveRange{x, y} => write!(f, "LD [I], V{x:X}-V{y:X}"),
+            Op::LoadRange{x, y} => write!(f, "LD V{x:X}-V{y:X}, [I]"),
+            Op::SetByte{x, byte} => write!(f, "LD V{x:X}, {byte:#04X}"),
+            Op::AddByte{x, byte} => write!(f, "ADD V{x:X}, {byte:#04X}"),
+            Op::Set{x, y} => write!(f, "LD V{x:X}, V{y:X}"),
+            Op::Or{x, y} => write!(f, "OR V{x:X}, V{y:X}"),
+            Op::And{x, y} => write!(f, "AND V{x:X}, V{y:X}"),
+            Op::Xor{x, y} => write!(f, "XOR V{x:X}, V{y:X}"),
+            Op::Add{x, y} => write!(f, "ADD V{x:X}, V{y:X}"),
+            Op::Sub{x, y} => write!(f, "SUB V{x:X}, V{y:X}"),
+            Op::ShiftRight{x, y} => write!(f, "SHR V{x:X}, V{y:X}"),
+            Op::SubReverse{x, y} => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Op::ShiftLeft{x, y} => write!(f, "SHL V{x:X}, V{y:X}"),
+            Op::SkipIfNotEqual{x, y} => write!(f, "SNE V{x:X}, V{y:X}"),
+            Op::SetIndex(_) => write!(f, "LD I, {address}"),
+            Op::JumpOffset{..} => write!(f, "JP V0, {address}"),
+            Op::Random{x, mask} => write!(f, "RND V{x:X}, {mask:#04X}"),
+            Op::Draw{x, y, height} => write!(f, "DRW V{x:X}, V{y:X}, {height}"),
+            Op::SkipIfKey{x} => write!(f, "SKP V{x:X}"),
+            Op::SkipIfNotKey{x} => write!(f, "SKNP V{x:X}"),
+            Op::LongLoad => write!(f, "LD I, LONG"),
+            Op::SelectPlanes(planes) => write!(f, "PLANE {planes}"),
+            Op::LoadAudio => write!(f, "AUDIO"),
+            Op::GetDelay{x} => write!(f, "LD V{x:X}, DT"),
+            Op::WaitKey{x} => write!(f, "LD V{x:X}, K"),
+            Op::SetDelay{x} => write!(f, "LD DT, V{x:X}"),
+            Op::SetSound{x} => write!(f, "LD ST, V{x:X}"),
+            Op::AddIndex{x} => write!(f, "ADD I, V{x:X}"),
+            Op::SmallGlyph{x} => write!(f, "LD F, V{x:X}"),
+            Op::BigGlyph{x} => write!(f, "LD HF, V{x:X}"),
+            Op::Bcd{x} => write!(f, "LD B, V{x:X}"),
+            Op::SetPitch{x} => write!(f, "PITCH V{x:X}"),
+            Op::Store{x} => write!(f, "LD [I], V{x:X}"),
+            Op::Load{x} => write!(f, "LD V{x:X}, [I]"),
+            Op::StoreFlags{x} => write!(f, "LD R, V{x:X}"),
+            Op::LoadFlags{x} => write!(f, "LD V{x:X}, R"),
+        }
+    }
+}
+
+impl fmt::Display for DecodedInstruction{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        self.formatted(Syntax::Octo, None).fmt(f)
+    }
+}
+
+pub struct Formatted<'a>{
+    instruction: &'a DecodedInstruction,
+    syntax: Syntax,
+    label: Option<&'a str>,
+}
+
+impl fmt::Display for Formatted<'_>{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        let address = match self.label{
+            Some(label) => label.to_string(),
+            None => format!("{:#05X}", self.instruction.opcode & 0xFFF),
+        };
+        match self.syntax{
+            Syntax::Octo => self.instruction.fmt_octo(f, &address),
+            Syntax::Cowgod => self.instruction.fmt_cowgod(f, &address),
+        }
+    }
+}
+
+impl FromStr for Syntax{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        match s{
+            "octo" => Ok(Syntax::Octo),
+            "cowgod" => Ok(Syntax::Cowgod),
+            _ => Err(format!("unknown syntax `{s}`, expected octo or cowgod")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LabelKind{
+    Entry,
+    Subroutine,
+    Jump,
+    Data,
+}
+
+// A ROM split into code reachable from its entry point and everything else,
+// which is treated as data.
+pub struct Listing<'a>{
+    rom: &'a [u8],
+    origin: u16,
+    // Start addresses of reachable instructions.
+    code: BTreeSet<u16>,
+    labels: BTreeMap<u16, LabelKind>,
+    syntax: Syntax,
+}
+
+impl<'a> Listing<'a>{
+    pub fn new(rom: &'a [u8], origin: u16, syntax: Syntax) -> Self{
+        let mut listing = Self{
+            rom,
+            origin,
+            code: BTreeSet::new(),
+            labels: BTreeMap::new(),
+            syntax,
+        };
+        listing.walk();
+        listing
+    }
+
+    fn contains(&self, address: u16) -> bool{
+        let end = self.origin as usize + self.rom.len();
+        (self.origin as usize..end).contains(&(address as usize))
+    }
+
+    fn word_at(&self, address: u16) -> Option<u16>{
+        let offset = address.checked_sub(self.origin)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn add_label(&mut self, address: u16, kind: LabelKind){
+        if self.contains(address){
+            // Code labels win over data labels for the same address.
+            let existing = self.labels.entry(address).or_insert(kind);
+            if *existing == LabelKind::Data{
+                *existing = kind;
+            }
+        }
+    }
+
+    fn walk(&mut self){
+        self.add_label(self.origin, LabelKind::Entry);
+        let mut pending = vec![self.origin];
+
+        while let Some(address) = pending.pop(){
+            if self.code.contains(&address){
+                continue;
+            }
+            let Some(opcode) = self.word_at(address) else {
+                continue;
+            };
+            let instruction = disassemble(opcode);
+            if !instruction.is_valid(){
+                continue;
+            }
+            self.code.insert(address);
+            if let Some(target) = instruction.index_target(){
+                self.add_label(target, LabelKind::Data);
+            }
+
+            let next = address.wrapping_add(instruction.size());
+            match instruction.flow(){
+                Flow::Next => pending.push(next),
+                Flow::LongLoad => {
+                    if let Some(target) = self.word_at(address.wrapping_add(2)){
+                        self.add_label(target, LabelKind::Data);
+                    }
+                    pending.push(next);
+                },
+                Flow::Skip => {
+                    pending.push(next);
+                    let skipped_len = self.word_at(next).map_or(2, |opcode| disassemble(opcode).size());
+                    pending.push(next.wrapping_add(skipped_len));
+                },
+                Flow::Jump(target) => {
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                },
+                // Usually a table of jumps indexed by V0.
+                Flow::JumpOffset(target) => {
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                },
+                Flow::Call(target) => {
+                    self.add_label(target, LabelKind::Subroutine);
+                    pending.push(target);
+                    pending.push(next);
+                },
+                Flow::Return | Flow::Exit | Flow::Invalid => (),
+            }
+        }
+    }
+
+    fn label(&self, address: u16) -> Option<String>{
+        let kind = self.labels.get(&address)?;
+        let name = match kind{
+            LabelKind::Entry => return Some("main".to_string()),
+            LabelKind::Subroutine => "sub",
+            LabelKind::Jump => "label",
+            LabelKind::Data => "data",
+        };
+        Some(format!("{name}_{address:03X}"))
+    }
+
+    fn write_label(&self, f: &mut fmt::Formatter<'_>, label: &str) -> fmt::Result{
+        match self.syntax{
+            Syntax::Octo => writeln!(f, ": {label}"),
+            Syntax::Cowgod => writeln!(f, "{label}:"),
+        }
+    }
+
+    // An instruction is only printed as code if nothing else starts inside it.
+    fn fits(&self, address: u16, len: u16) -> bool{
+        (1..len).all(|offset| {
+            let inner = address.wrapping_add(offset);
+            !self.code.contains(&inner) && !self.labels.contains_key(&inner)
+        })
+    }
+
+    fn write_data(&self, f: &mut fmt::Formatter<'_>, address: u16, bytes: &[u8]) -> fmt::Result{
+        let values: Vec<String> = bytes.iter().map(|byte| format!("{byte:#04X}")).collect();
+        match self.syntax{
+            Syntax::Octo => writeln!(f, "\t{}  # {address:03X}", values.join(" ")),
+            Syntax::Cowgod => writeln!(f, "\tDB {}  ; {address:03X}", values.join(", ")),
+        }
+    }
+}
+
+impl fmt::Display for Listing<'_>{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        let end = self.origin as usize + self.rom.len();
+        let mut address = self.origin as usize;
+        let mut data: Vec<u8> = Vec::new();
+        let mut data_start = address;
+
+        while address < end{
+            let current = address as u16;
+            let label = self.label(current);
+            let instruction = self.word_at(current).map(disassemble);
+            let code = instruction.filter(|instruction| {
+                self.code.contains(&current) && self.fits(current, instruction.size())
+            });
+
+            if (label.is_some() || code.is_some() || data.len() == DATA_BYTES_PER_LINE) && !data.is_empty(){
+                self.write_data(f, data_start as u16, &data)?;
+                data.clear();
+            }
+            if let Some(label) = &label{
+                self.write_label(f, label)?;
+            }
+
+            let Some(instruction) = code else {
+                if data.is_empty(){
+                    data_start = address;
+                }
+                data.push(self.rom[address - self.origin as usize]);
+                address += 1;
+                continue;
+            };
+
+            let target = match instruction.flow(){
+                Flow::Jump(target) | Flow::JumpOffset(target) | Flow::Call(target) => Some(target),
+                _ => instruction.index_target(),
+            };
+            let target_label = target.and_then(|target| self.label(target));
+            let mut text = instruction.formatted(self.syntax, target_label.as_deref()).to_string();
+            if instruction.flow() == Flow::LongLoad{
+                let operand = self.word_at(current.wrapping_add(2)).unwrap_or(0);
+                let operand = self.label(operand).unwrap_or_else(|| format!("{operand:#06X}"));
+                text = format!("{text} {operand}");
+            }
+
+            let comment = match self.syntax{
+                Syntax::Octo => '#',
+                Syntax::Cowgod => ';',
+            };
+            writeln!(f, "\t{text:<28}{comment} {current:03X}: {:04X}", instruction.opcode)?;
+            address += instruction.size() as usize;
+        }
+        if !data.is_empty(){
+            self.write_data(f, data_start as u16, &data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn octo(opcode: u16) -> String{
+        disassemble(opcode).to_string()
+    }
+
+    fn cowgod(opcode: u16) -> String{
+        disassemble(opcode).formatted(Syntax::Cowgod, None).to_string()
+    }
+
+    #[test]
+    fn chip8_mnemonics(){
+        let cases = [
+            (0x00E0, "clear", "CLS"),
+            (0x00EE, "return", "RET"),
+            (0x1234, "jump 0x234", "JP 0x234"),
+            (0x2345, ":call 0x345", "CALL 0x345"),
+            (0x3A12, "if va != 0x12 then", "SE VA, 0x12"),
+            (0x4B34, "if vb == 0x34 then", "SNE VB, 0x34"),
+            (0x5120, "if v1 != v2 then", "SE V1, V2"),
+            (0x6C56, "vc := 0x56", "LD VC, 0x56"),
+            (0x7D01, "vd += 0x01", "ADD VD, 0x01"),
+            (0x8120, "v1 := v2", "LD V1, V2"),
+            (0x8121, "v1 |= v2", "OR V1, V2"),
+            (0x8122, "v1 &= v2", "AND V1, V2"),
+            (0x8123, "v1 ^= v2", "XOR V1, V2"),
+            (0x8124, "v1 += v2", "ADD V1, V2"),
+            (0x8125, "v1 -= v2", "SUB V1, V2"),
+            (0x8126, "v1 >>= v2", "SHR V1, V2"),
+            (0x8127, "v1 =- v2", "SUBN V1, V2"),
+            (0x812E, "v1 <<= v2", "SHL V1, V2"),
+            (0x9120, "if v1 == v2 then", "SNE V1, V2"),
+            (0xA2F0, "i := 0x2F0", "LD I, 0x2F0"),
+            (0xB300, "jump0 0x300", "JP V0, 0x300"),
+            (0xC40F, "v4 := random 0x0F", "RND V4, 0x0F"),
+            (0xD125, "sprite v1 v2 5", "DRW V1, V2, 5"),
+            (0xE59E, "if v5 -key then", "SKP V5"),
+            (0xE5A1, "if v5 key then", "SKNP V5"),
+            (0xF607, "v6 := delay", "LD V6, DT"),
+            (0xF60A, "v6 := key", "LD V6, K"),
+            (0xF615, "delay := v6", "LD DT, V6"),
+            (0xF618, "buzzer := v6", "LD ST, V6"),
+            (0xF61E, "i += v6", "ADD I, V6"),
+            (0xF629, "i := hex v6", "LD F, V6"),
+            (0xF633, "bcd v6", "LD B, V6"),
+            (0xF655, "save v6", "LD [I], V6"),
+            (0xF665, "load v6", "LD V6, [I]"),
+        ];
+        for (opcode, expected_octo, expected_cowgod) in cases{
+            assert_eq!(octo(opcode), expected_octo, "{opcode:04X}");
+            assert_eq!(cowgod(opcode), expected_cowgod, "{opcode:04X}");
+        }
+    }
+
+    #[test]
+    fn schip_mnemonics(){
+        let cases = [
+            (0x00C4, "scroll-down 4", "SCD 4"),
+            (0x00FB, "scroll-right", "SCR"),
+            (0x00FC, "scroll-left", "SCL"),
+            (0x00FD, "exit", "EXIT"),
+            (0x00FE, "lores", "LOW"),
+            (0x00FF, "hires", "HIGH"),
+            (0xD120, "sprite v1 v2 0", "DRW V1, V2, 0"),
+            (0xF730, "i := bighex v7", "LD HF, V7"),
+            (0xF775, "saveflags v7", "LD R, V7"),
+            (0xF785, "loadflags v7", "LD V7, R"),
+        ];
+        for (opcode, expected_octo, expected_cowgod) in cases{
+            assert_eq!(octo(opcode), expected_octo, "{opcode:04X}");
+            assert_eq!(cowgod(opcode), expected_cowgod, "{opcode:04X}");
+        }
+    }
+
+    #[test]
+    fn xo_chip_mnemonics(){
+        let cases = [
+            (0x00D3, "scroll-up 3", "SCU 3"),
+            (0x5122, "save v1 - v2", "LD [I], V1-V2"),
+            (0x5123, "load v1 - v2", "LD V1-V2, [I]"),
+            (0xF000, "i := long", "LD I, LONG"),
+            (0xF201, "plane 2", "PLANE 2"),
+            (0xF002, "audio", "AUDIO"),
+            (0xF83A, "pitch := v8", "PITCH V8"),
+        ];
+        for (opcode, expected_octo, expected_cowgod) in cases{
+            assert_eq!(octo(opcode), expected_octo, "{opcode:04X}");
+            assert_eq!(cowgod(opcode), expected_cowgod, "{opcode:04X}");
+        }
+        assert_eq!(disassemble(0xF000).size(), 4);
+    }
+
+    #[test]
+    fn unknown_words(){
+        for opcode in [0x0123, 0x5121, 0x8128, 0xE100, 0xF0FF]{
+            let instruction = disassemble(opcode);
+            assert!(!instruction.is_valid(), "{opcode:04X}");
+            assert_eq!(instruction.flow(), Flow::Invalid);
+        }
+        assert_eq!(octo(0x8128), "0x81 0x28");
+        assert_eq!(cowgod(0x8128), "DW 0x8128");
+    }
+
+    #[test]
+    fn labels_for_targets(){
+        // Calls 0x206, jumps to 0x20A, the subroutine returns, 0x20A spins.
+        let rom = [0x22, 0x06, 0x12, 0x0A, 0x00, 0x00, 0xA2, 0x0C, 0x00, 0xEE, 0x12, 0x0A, 0xF0, 0x0F];
+        let listing = Listing::new(&rom, 0x200, Syntax::Octo).to_string();
+        let lines: Vec<&str> = listing.lines().map(str::trim).collect();
+        assert_eq!(lines, [
+            ": main",
+            ":call sub_206               # 200: 2206",
+            "jump label_20A              # 202: 120A",
+            "0x00 0x00  # 204",
+            ": sub_206",
+            "i := data_20C               # 206: A20C",
+            "return                      # 208: 00EE",
+            ": label_20A",
+            "jump label_20A              # 20A: 120A",
+            ": data_20C",
+            "0xF0 0x0F  # 20C",
+        ]);
+    }
+}
@@ -0,0 +1,131 @@
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// Each pixel holds a bitmask of the XO-CHIP planes it is lit in, so plain
+// CHIP-8 and SUPER-CHIP only ever produce 0 and 1.
+pub struct Display{
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Display{
+    pub fn new() -> Self{
+        Self{
+            width: LORES_WIDTH,
+            height: LORES_HEIGHT,
+            pixels: vec![0; LORES_WIDTH * LORES_HEIGHT],
+        }
+    }
+
+    pub(crate) fn from_parts(width: usize, height: usize, pixels: Vec<u8>) -> Self{
+        assert_eq!(pixels.len(), width * height);
+        Self{width, height, pixels}
+    }
+
+    pub fn width(&self) -> usize{
+        self.width
+    }
+
+    pub fn height(&self) -> usize{
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8]{
+        &self.pixels
+    }
+
+    pub fn is_hires(&self) -> bool{
+        self.width == HIRES_WIDTH
+    }
+
+    pub fn set_hires(&mut self, hires: bool){
+        (self.width, self.height) = if hires{
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (LORES_WIDTH, LORES_HEIGHT)
+        };
+        self.pixels = vec![0; self.width * self.height];
+    }
+
+    pub fn clear(&mut self, planes: u8){
+        for pixel in self.pixels.iter_mut(){
+            *pixel &= !planes;
+        }
+    }
+
+    // Sprites are `sprite_width` pixels wide, one bit per pixel, MSB first,
+    // and are XORed into a single plane. Returns whether any lit pixel of
+    // that plane was turned off.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], sprite_width: usize, clip: bool, plane: u8) -> bool{
+        let x = x % self.width;
+        let y = y % self.height;
+        let bytes_per_row = sprite_width / 8;
+        let mut collision = false;
+
+        for (row, bytes) in sprite.chunks(bytes_per_row).enumerate(){
+            for col in 0..sprite_width{
+                let sprite_pixel = (bytes[col / 8] >> (7 - col % 8)) & 1;
+                if sprite_pixel == 0{
+                    continue;
+                }
+
+                let x_pos = x + col;
+                let y_pos = y + row;
+                if clip && (x_pos >= self.width || y_pos >= self.height){
+                    continue;
+                }
+
+                let pixel_idx = x_pos % self.width + (y_pos % self.height) * self.width;
+                if self.pixels[pixel_idx] & plane != 0{
+                    collision = true;
+                }
+                self.pixels[pixel_idx] ^= plane;
+            }
+        }
+        collision
+    }
+
+    pub fn scroll_down(&mut self, rows: usize, planes: u8){
+        self.scroll(0, rows as isize, planes);
+    }
+
+    pub fn scroll_up(&mut self, rows: usize, planes: u8){
+        self.scroll(0, -(rows as isize), planes);
+    }
+
+    pub fn scroll_left(&mut self, cols: usize, planes: u8){
+        self.scroll(-(cols as isize), 0, planes);
+    }
+
+    pub fn scroll_right(&mut self, cols: usize, planes: u8){
+        self.scroll(cols as isize, 0, planes);
+    }
+
+    fn scroll(&mut self, dx: isize, dy: isize, planes: u8){
+        let old = self.pixels.clone();
+        for y in 0..self.height{
+            for x in 0..self.width{
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let in_bounds = (0..self.width as isize).contains(&src_x) && (0..self.height as isize).contains(&src_y);
+                let moved = if in_bounds{
+                    old[src_x as usize + src_y as usize * self.width] & planes
+                } else {
+                    0
+                };
+
+                let pixel_idx = x + y * self.width;
+                self.pixels[pixel_idx] = (old[pixel_idx] & !planes) | moved;
+            }
+        }
+    }
+}
+
+impl Default for Display{
+    fn default() -> Self{
+        Self::new()
+    }
+}
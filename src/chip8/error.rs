@@ -0,0 +1,172 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum LoadError{
+    // The path is known when the ROM was read from a file.
+    Empty{
+        path: Option<PathBuf>,
+    },
+    TooLarge{
+        path: Option<PathBuf>,
+        size: usize,
+        max: usize,
+    },
+    Unreadable{
+        path: PathBuf,
+        source: io::Error,
+    },
+    FontOutOfBounds{
+        address: u16,
+        len: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadWarning{
+    OddLength{
+        size: usize,
+    },
+}
+
+impl fmt::Display for LoadError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            LoadError::Empty{path} => write!(f, "ROM{} is empty", display_path(path)),
+            LoadError::TooLarge{path, size, max} => {
+                write!(f, "ROM{} is {size} bytes, but at most {max} bytes fit in memory", display_path(path))
+            },
+            LoadError::Unreadable{path, source} if source.kind() == io::ErrorKind::NotFound => {
+                write!(f, "ROM file `{}` does not exist", path.display())
+            },
+            LoadError::Unreadable{path, source} => {
+                write!(f, "could not read ROM file `{}`: {source}", path.display())
+            },
+            LoadError::FontOutOfBounds{address, len} => {
+                write!(f, "a {len}-byte font does not fit in memory at {address:#05X}")
+            },
+        }
+    }
+}
+
+fn display_path(path: &Option<PathBuf>) -> String{
+    match path{
+        Some(path) => format!(" `{}`", path.display()),
+        None => String::new(),
+    }
+}
+
+impl std::error::Error for LoadError{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>{
+        match self{
+            LoadError::Unreadable{source, ..} => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LoadWarning{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            LoadWarning::OddLength{size} => {
+                write!(f, "ROM is {size} bytes long, an odd length for 2-byte instructions")
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFault{
+    pub pc: u16,
+    pub opcode: u16,
+    pub kind: FaultKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind{
+    UnknownOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds{
+        address: usize,
+    },
+    PcOutOfBounds,
+}
+
+impl fmt::Display for FaultKind{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            FaultKind::UnknownOpcode => write!(f, "unknown opcode"),
+            FaultKind::StackOverflow => write!(f, "stack overflow"),
+            FaultKind::StackUnderflow => write!(f, "return with an empty stack"),
+            FaultKind::MemoryOutOfBounds{address} => write!(f, "memory access out of bounds at {address:#05X}"),
+            FaultKind::PcOutOfBounds => write!(f, "program counter ran past the end of memory"),
+        }
+    }
+}
+
+impl fmt::Display for CpuFault{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "{} at {:#05X} (opcode {:04X})", self.kind, self.pc, self.opcode)
+    }
+}
+
+impl std::error::Error for CpuFault{}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidOpcode(pub u16);
+
+impl fmt::Display for InvalidOpcode{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "invalid opcode {:04X}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidOpcode{}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError{
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError{}
+
+#[derive(Debug)]
+pub enum SnapshotError{
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    TrailingBytes(usize),
+    Invalid(&'static str),
+    Io(io::Error),
+}
+
+impl fmt::Display for SnapshotError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            SnapshotError::BadMagic => write!(f, "not a pico8 save state"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported save state version {version}"),
+            SnapshotError::Truncated => write!(f, "save state is truncated"),
+            SnapshotError::TrailingBytes(len) => write!(f, "save state has {len} unexpected bytes at the end"),
+            SnapshotError::Invalid(field) => write!(f, "save state has an invalid {field}"),
+            SnapshotError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>{
+        match self{
+            SnapshotError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,150 @@
+pub const DEFAULT_FONT_ADDRESS: u16 = 0x50;
+pub const DEFAULT_BIG_FONT_ADDRESS: u16 = 0xA0;
+
+pub struct Font{
+    pub name: &'static str,
+    pub glyph_len: u16,
+    pub data: &'static [u8],
+}
+
+pub const CHIP48_FONT: Font = Font{
+    name: "chip48",
+    glyph_len: 5,
+    data: &[
+        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+        0x20, 0x60, 0x20, 0x20, 0x70, // 1
+        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+        0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+    ],
+};
+
+pub const VIP_FONT: Font = Font{
+    name: "vip",
+    glyph_len: 5,
+    data: &[
+        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+        0x60, 0x20, 0x20, 0x20, 0x70, // 1
+        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+        0xF0, 0x10, 0x70, 0x10, 0xF0, // 3
+        0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+        0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+        0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+        0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+        0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+    ],
+};
+
+pub const DREAM6800_FONT: Font = Font{
+    name: "dream6800",
+    glyph_len: 5,
+    data: &[
+        0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+        0x40, 0x40, 0x40, 0x40, 0x40, // 1
+        0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+        0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+        0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+        0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+        0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+        0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+        0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+        0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+        0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+        0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+        0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+        0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+        0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+        0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+    ],
+};
+
+pub const ETI660_FONT: Font = Font{
+    name: "eti660",
+    glyph_len: 5,
+    data: &[
+        0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+        0x20, 0x20, 0x20, 0x20, 0x20, // 1
+        0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+        0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+        0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+        0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+        0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+        0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+        0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+        0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+        0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+        0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+        0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+        0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+        0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+        0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+    ],
+};
+
+pub const SCHIP_BIG_FONT: Font = Font{
+    name: "schip",
+    glyph_len: 10,
+    data: &[
+        0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+        0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+        0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+        0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+        0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+        0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+        0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+        0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+        0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+        0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+        0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+    ],
+};
+
+pub const SMALL_FONTS: [&Font; 4] = [&CHIP48_FONT, &VIP_FONT, &DREAM6800_FONT, &ETI660_FONT];
+
+impl Font{
+    pub fn small_by_name(name: &str) -> Result<&'static Font, String>{
+        SMALL_FONTS
+            .into_iter()
+            .find(|font| font.name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = SMALL_FONTS.iter().map(|font| font.name).collect();
+                format!("unknown font `{name}`, expected one of: {}", names.join(", "))
+            })
+    }
+}
+
+// Where a font was loaded, so FX29/FX30 can find its glyphs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FontSlot{
+    pub address: u16,
+    pub glyph_len: u16,
+}
+
+impl FontSlot{
+    pub fn glyph_address(&self, digit: u8) -> u16{
+        self.address.wrapping_add((digit & 0xF) as u16 * self.glyph_len)
+    }
+}
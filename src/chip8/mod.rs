@@ -1 +1,14 @@
+pub mod assembler;
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod font;
+pub mod op;
+pub mod quirks;
+pub mod rewind;
+pub mod scheduler;
+pub mod snapshot;
+pub mod symbols;
+pub mod trace;
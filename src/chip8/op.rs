@@ -0,0 +1,217 @@
+use crate::chip8::error::InvalidOpcode;
+
+pub const LONG_LOAD_OPCODE: u16 = 0xF000;
+
+pub struct Instruction{
+    pub opcode: [u8; 2]
+}
+
+impl From<u16> for Instruction{
+    fn from(opcode: u16) -> Self{
+        Self{opcode: opcode.to_be_bytes()}
+    }
+}
+
+impl Instruction{
+    pub fn get_nibble(&self, idx: usize) -> u8{
+        assert!(idx < 4);
+        let byte = self.opcode[idx / 2];
+        if idx.is_multiple_of(2){
+            (byte >> 4) & 0xF
+        } else {
+            byte & 0xF
+        }
+    }
+
+    pub fn get_address(&self) -> u16{
+        let instruction = self.get_u16_instruction();
+        instruction & 0xFFF
+    }
+
+    pub fn get_u16_instruction(&self) -> u16{
+        ((self.opcode[0] as u16) << 8) | (self.opcode[1] as u16)
+    }
+
+    pub fn decode(&self) -> Result<Op, InvalidOpcode>{
+        let x = self.get_nibble(1);
+        let y = self.get_nibble(2);
+        let n = self.get_nibble(3);
+        let byte = self.opcode[1];
+        let address = self.get_address();
+        let invalid = InvalidOpcode(self.get_u16_instruction());
+
+        let op = match self.get_nibble(0){
+            0x0 => match address{
+                0x0E0 => Op::Clear,
+                0x0EE => Op::Return,
+                0x0FB => Op::ScrollRight,
+                0x0FC => Op::ScrollLeft,
+                0x0FD => Op::Exit,
+                0x0FE => Op::Lores,
+                0x0FF => Op::Hires,
+                _ if address & 0xFF0 == 0x0C0 => Op::ScrollDown(n),
+                _ if address & 0xFF0 == 0x0D0 => Op::ScrollUp(n),
+                _ => return Err(invalid),
+            },
+            0x1 => Op::Jump(address),
+            0x2 => Op::Call(address),
+            0x3 => Op::SkipIfEqualByte{x, byte},
+            0x4 => Op::SkipIfNotEqualByte{x, byte},
+            0x5 => match n{
+                0x0 => Op::SkipIfEqual{x, y},
+                0x2 => Op::SaveRange{x, y},
+                0x3 => Op::LoadRange{x, y},
+                _ => return Err(invalid),
+            },
+            0x6 => Op::SetByte{x, byte},
+            0x7 => Op::AddByte{x, byte},
+            0x8 => match n{
+                0x0 => Op::Set{x, y},
+                0x1 => Op::Or{x, y},
+                0x2 => Op::And{x, y},
+                0x3 => Op::Xor{x, y},
+                0x4 => Op::Add{x, y},
+                0x5 => Op::Sub{x, y},
+                0x6 => Op::ShiftRight{x, y},
+                0x7 => Op::SubReverse{x, y},
+                0xE => Op::ShiftLeft{x, y},
+                _ => return Err(invalid),
+            },
+            0x9 if n == 0 => Op::SkipIfNotEqual{x, y},
+            0xA => Op::SetIndex(address),
+            0xB => Op::JumpOffset{x, address},
+            0xC => Op::Random{x, mask: byte},
+            0xD => Op::Draw{x, y, height: n},
+            0xE => match byte{
+                0x9E => Op::SkipIfKey{x},
+                0xA1 => Op::SkipIfNotKey{x},
+                _ => return Err(invalid),
+            },
+            0xF => match byte{
+                0x00 if x == 0 => Op::LongLoad,
+                0x01 => Op::SelectPlanes(x),
+                0x02 if x == 0 => Op::LoadAudio,
+                0x07 => Op::GetDelay{x},
+                0x0A => Op::WaitKey{x},
+                0x15 => Op::SetDelay{x},
+                0x18 => Op::SetSound{x},
+                0x1E => Op::AddIndex{x},
+                0x29 => Op::SmallGlyph{x},
+                0x30 => Op::BigGlyph{x},
+                0x33 => Op::Bcd{x},
+                0x3A => Op::SetPitch{x},
+                0x55 => Op::Store{x},
+                0x65 => Op::Load{x},
+                0x75 => Op::StoreFlags{x},
+                0x85 => Op::LoadFlags{x},
+                _ => return Err(invalid),
+            },
+            _ => return Err(invalid),
+        };
+        Ok(op)
+    }
+}
+
+// A decoded instruction. `x` and `y` are register numbers, everything else
+// is an immediate operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op{
+    Clear,
+    Return,
+    ScrollDown(u8),
+    ScrollUp(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    Lores,
+    Hires,
+    Jump(u16),
+    Call(u16),
+    SkipIfEqualByte{x: u8, byte: u8},
+    SkipIfNotEqualByte{x: u8, byte: u8},
+    SkipIfEqual{x: u8, y: u8},
+    SaveRange{x: u8, y: u8},
+    LoadRange{x: u8, y: u8},
+    SetByte{x: u8, byte: u8},
+    AddByte{x: u8, byte: u8},
+    Set{x: u8, y: u8},
+    Or{x: u8, y: u8},
+    And{x: u8, y: u8},
+    Xor{x: u8, y: u8},
+    Add{x: u8, y: u8},
+    Sub{x: u8, y: u8},
+    ShiftRight{x: u8, y: u8},
+    SubReverse{x: u8, y: u8},
+    ShiftLeft{x: u8, y: u8},
+    SkipIfNotEqual{x: u8, y: u8},
+    SetIndex(u16),
+    // BNNN, offset by V0 or by VX depending on the jump quirk.
+    JumpOffset{x: u8, address: u16},
+    Random{x: u8, mask: u8},
+    // A height of 0 draws a 16x16 sprite when SCHIP opcodes are enabled.
+    Draw{x: u8, y: u8, height: u8},
+    SkipIfKey{x: u8},
+    SkipIfNotKey{x: u8},
+    // F000 NNNN, the address is the next word in memory.
+    LongLoad,
+    SelectPlanes(u8),
+    LoadAudio,
+    GetDelay{x: u8},
+    WaitKey{x: u8},
+    SetDelay{x: u8},
+    SetSound{x: u8},
+    AddIndex{x: u8},
+    SmallGlyph{x: u8},
+    BigGlyph{x: u8},
+    Bcd{x: u8},
+    SetPitch{x: u8},
+    Store{x: u8},
+    Load{x: u8},
+    StoreFlags{x: u8},
+    LoadFlags{x: u8},
+}
+
+impl Op{
+    // Size in memory, including the operand word of a long load.
+    pub fn size(&self) -> u16{
+        if *self == Op::LongLoad { 4 } else { 2 }
+    }
+
+    pub fn is_skip(&self) -> bool{
+        matches!(self,
+            Op::SkipIfEqualByte{..}
+            | Op::SkipIfNotEqualByte{..}
+            | Op::SkipIfEqual{..}
+            | Op::SkipIfNotEqual{..}
+            | Op::SkipIfKey{..}
+            | Op::SkipIfNotKey{..}
+        )
+    }
+
+    // Added by SUPER-CHIP. 16x16 sprites are too, but DXY0 is valid everywhere.
+    pub fn is_schip(&self) -> bool{
+        matches!(self,
+            Op::ScrollDown(_)
+            | Op::ScrollRight
+            | Op::ScrollLeft
+            | Op::Exit
+            | Op::Lores
+            | Op::Hires
+            | Op::BigGlyph{..}
+            | Op::StoreFlags{..}
+            | Op::LoadFlags{..}
+        )
+    }
+
+    pub fn is_xo_chip(&self) -> bool{
+        matches!(self,
+            Op::ScrollUp(_)
+            | Op::SaveRange{..}
+            | Op::LoadRange{..}
+            | Op::LongLoad
+            | Op::SelectPlanes(_)
+            | Op::LoadAudio
+            | Op::SetPitch{..}
+        )
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStoreQuirk{
+    Unchanged,
+    IncrementByX,
+    IncrementByXPlusOne,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks{
+    pub shift_uses_vy: bool,
+    pub load_store: LoadStoreQuirk,
+    pub jump_uses_vx: bool,
+    pub logic_resets_vf: bool,
+    pub clip_sprites: bool,
+    pub extended_memory: bool,
+    // SCHIP scrolling, hires, exit, big font and flag register opcodes and
+    // 16x16 sprites.
+    pub schip_opcodes: bool,
+    // XO-CHIP long load, planes, audio, pitch, register range and scroll up
+    // opcodes. Skips also step over the whole 4-byte long load.
+    pub xo_chip_opcodes: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirkProfile{
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+    Modern,
+}
+
+impl Quirks{
+    pub fn cosmac_vip() -> Self{
+        Self{
+            shift_uses_vy: true,
+            load_store: LoadStoreQuirk::IncrementByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            extended_memory: false,
+            schip_opcodes: false,
+            xo_chip_opcodes: false,
+        }
+    }
+
+    pub fn chip48() -> Self{
+        Self{
+            shift_uses_vy: false,
+            load_store: LoadStoreQuirk::IncrementByX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            extended_memory: false,
+            schip_opcodes: false,
+            xo_chip_opcodes: false,
+        }
+    }
+
+    pub fn superchip() -> Self{
+        Self{
+            shift_uses_vy: false,
+            load_store: LoadStoreQuirk::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            extended_memory: false,
+            schip_opcodes: true,
+            xo_chip_opcodes: false,
+        }
+    }
+
+    pub fn modern() -> Self{
+        Self{
+            shift_uses_vy: false,
+            load_store: LoadStoreQuirk::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            extended_memory: false,
+            schip_opcodes: true,
+            xo_chip_opcodes: false,
+        }
+    }
+
+    pub fn xochip() -> Self{
+        Self{
+            shift_uses_vy: true,
+            load_store: LoadStoreQuirk::IncrementByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            extended_memory: true,
+            schip_opcodes: true,
+            xo_chip_opcodes: true,
+        }
+    }
+
+    pub fn memory_size(&self) -> usize{
+        if self.extended_memory{
+            0x10000
+        } else {
+            0x1000
+        }
+    }
+}
+
+impl Default for Quirks{
+    fn default() -> Self{
+        Self::modern()
+    }
+}
+
+impl QuirkProfile{
+    pub const ALL: [QuirkProfile; 5] = [
+        QuirkProfile::CosmacVip,
+        QuirkProfile::Chip48,
+        QuirkProfile::SuperChip,
+        QuirkProfile::XoChip,
+        QuirkProfile::Modern,
+    ];
+
+    pub fn name(&self) -> &'static str{
+        match self{
+            QuirkProfile::CosmacVip => "vip",
+            QuirkProfile::Chip48 => "chip48",
+            QuirkProfile::SuperChip => "schip",
+            QuirkProfile::XoChip => "xochip",
+            QuirkProfile::Modern => "modern",
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks{
+        match self{
+            QuirkProfile::CosmacVip => Quirks::cosmac_vip(),
+            QuirkProfile::Chip48 => Quirks::chip48(),
+            QuirkProfile::SuperChip => Quirks::superchip(),
+            QuirkProfile::XoChip => Quirks::xochip(),
+            QuirkProfile::Modern => Quirks::modern(),
+        }
+    }
+}
+
+impl fmt::Display for QuirkProfile{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for QuirkProfile{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        QuirkProfile::ALL
+            .into_iter()
+            .find(|profile| profile.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = QuirkProfile::ALL.iter().map(|profile| profile.name()).collect();
+                format!("unknown quirk profile `{s}`, expected one of: {}", names.join(", "))
+            })
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+
+use crate::chip8::scheduler::FRAMES_PER_SECOND;
+use crate::chip8::snapshot::Snapshot;
+
+pub const DEFAULT_REWIND_SECONDS: u32 = 10;
+pub const MAX_REWIND_SECONDS: u32 = 600;
+
+// Runs shorter than this are cheaper to store as literals.
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 130;
+const MAX_LITERAL: usize = 128;
+
+// Ring buffer of compressed snapshots, one per captured frame. Once full the
+// oldest frame is dropped, so it always covers the most recent stretch of play.
+pub struct RewindBuffer{
+    frames: VecDeque<Vec<u8>>,
+    capacity: usize,
+}
+
+impl RewindBuffer{
+    // Frames are allocated as they come in, the capacity is only a limit.
+    pub fn new(capacity: usize) -> Self{
+        Self{
+            frames: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn with_seconds(seconds: u32) -> Self{
+        Self::new(seconds as usize * FRAMES_PER_SECOND as usize)
+    }
+
+    pub fn capacity(&self) -> usize{
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize{
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.frames.is_empty()
+    }
+
+    // Total compressed size of the buffered frames.
+    pub fn size_in_bytes(&self) -> usize{
+        self.frames.iter().map(Vec::len).sum()
+    }
+
+    pub fn clear(&mut self){
+        self.frames.clear();
+    }
+
+    pub fn push(&mut self, snapshot: &Snapshot){
+        if self.capacity == 0{
+            return;
+        }
+        if self.frames.len() == self.capacity{
+            self.frames.pop_front();
+        }
+        self.frames.push_back(compress(&snapshot.to_bytes()));
+    }
+
+    // Takes the most recent frame off the buffer, stepping one frame back.
+    pub fn pop(&mut self) -> Option<Snapshot>{
+        let frame = self.frames.pop_back()?;
+        let snapshot = Snapshot::from_bytes(&decompress(&frame))
+            .expect("rewind buffer only holds snapshots it encoded itself");
+        Some(snapshot)
+    }
+}
+
+// PackBits-style run-length encoding. A control byte below 0x80 is followed by
+// that many plus one literal bytes; 0x80 and above repeats the next byte
+// `control - 0x80 + MIN_RUN` times. Memory and framebuffers are mostly zeros,
+// which is where nearly all of the savings come from.
+fn compress(bytes: &[u8]) -> Vec<u8>{
+    let mut out = Vec::with_capacity(bytes.len() / 4);
+    let mut literal_start = 0;
+    let mut position = 0;
+
+    while position < bytes.len(){
+        let value = bytes[position];
+        let run = bytes[position..].iter().take(MAX_RUN).take_while(|&&b| b == value).count();
+        if run < MIN_RUN{
+            position += 1;
+            continue;
+        }
+
+        flush_literals(&mut out, &bytes[literal_start..position]);
+        out.push((0x80 + run - MIN_RUN) as u8);
+        out.push(value);
+        position += run;
+        literal_start = position;
+    }
+    flush_literals(&mut out, &bytes[literal_start..]);
+    out
+}
+
+fn flush_literals(out: &mut Vec<u8>, literals: &[u8]){
+    for chunk in literals.chunks(MAX_LITERAL){
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn decompress(bytes: &[u8]) -> Vec<u8>{
+    let mut out = Vec::with_capacity(bytes.len() * 4);
+    let mut position = 0;
+
+    while position < bytes.len(){
+        let control = bytes[position] as usize;
+        position += 1;
+        if control < 0x80{
+            let end = (position + control + 1).min(bytes.len());
+            out.extend_from_slice(&bytes[position..end]);
+            position = end;
+        } else if let Some(&value) = bytes.get(position){
+            out.extend(std::iter::repeat_n(value, control - 0x80 + MIN_RUN));
+            position += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::backend::headless_backend::HeadlessBackend;
+    use crate::chip8::cpu::Cpu;
+
+    fn round_trip(bytes: &[u8]) -> Vec<u8>{
+        let compressed = compress(bytes);
+        assert_eq!(decompress(&compressed), bytes);
+        compressed
+    }
+
+    // Distinct neighbours, so none of it compresses into runs.
+    fn literals(len: usize) -> Vec<u8>{
+        (0..len).map(|idx| idx as u8).collect()
+    }
+
+    #[test]
+    fn empty(){
+        assert!(round_trip(&[]).is_empty());
+    }
+
+    #[test]
+    fn runs_at_the_limits(){
+        assert_eq!(round_trip(&[7; MIN_RUN - 1]), [0x01, 7, 7]);
+        assert_eq!(round_trip(&[7; MIN_RUN]), [0x80, 7]);
+        assert_eq!(round_trip(&[7; MAX_RUN]), [0xFF, 7]);
+        assert_eq!(round_trip(&[7; MAX_RUN + 1]), [0xFF, 7, 0x00, 7]);
+        assert_eq!(round_trip(&[7; MAX_RUN + MIN_RUN]), [0xFF, 7, 0x80, 7]);
+    }
+
+    #[test]
+    fn literals_at_the_limits(){
+        let compressed = round_trip(&literals(MAX_LITERAL));
+        assert_eq!(compressed.len(), MAX_LITERAL + 1);
+        assert_eq!(compressed[0], 0x7F);
+
+        let compressed = round_trip(&literals(MAX_LITERAL + 1));
+        assert_eq!(compressed.len(), MAX_LITERAL + 3);
+        assert_eq!(compressed[MAX_LITERAL + 1], 0x00);
+    }
+
+    #[test]
+    fn mixed_runs_and_literals(){
+        let mut bytes = literals(5);
+        bytes.extend([0; 200]);
+        bytes.extend(literals(MAX_LITERAL * 2 + 3));
+        bytes.extend([0xFF; MIN_RUN]);
+        bytes.extend([1, 2, 2, 3]);
+        bytes.extend([9; MAX_RUN * 3 + 1]);
+        let compressed = round_trip(&bytes);
+        assert!(compressed.len() < bytes.len());
+    }
+
+    #[test]
+    fn zero_capacity_keeps_nothing(){
+        let mut buffer = RewindBuffer::new(0);
+        buffer.push(&Cpu::new().snapshot());
+        assert!(buffer.is_empty());
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    fn full_buffer_drops_the_oldest(){
+        let mut cpu = Cpu::new();
+        // V0 = 1, V0 = 2, V0 = 3.
+        cpu.load_rom_bytes(&[0x60, 0x01, 0x60, 0x02, 0x60, 0x03]).unwrap();
+        let mut buffer = RewindBuffer::new(2);
+        for _ in 0..3{
+            cpu.step(&mut HeadlessBackend::new()).unwrap();
+            buffer.push(&cpu.snapshot());
+        }
+        assert_eq!(buffer.len(), 2);
+        let mut restored = Cpu::new();
+        for expected in [3, 2]{
+            restored.restore(&buffer.pop().unwrap());
+            assert_eq!(restored.registers()[0], expected);
+        }
+        assert!(buffer.pop().is_none());
+    }
+}
@@ -0,0 +1,309 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::backend::backend::Backend;
+use crate::chip8::cpu::{Cpu, StepOutcome};
+use crate::chip8::error::CpuFault;
+use crate::chip8::op::Op;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+// Roughly 700 instructions per second.
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 11;
+// Well past anything written for real hardware while a frame still runs in a
+// few milliseconds.
+pub const MAX_INSTRUCTIONS_PER_FRAME: u32 = 10_000;
+// How many late frames are run back to back after a stall, the rest are dropped
+// so a long hiccup doesn't turn into seconds of fast forward.
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+// Multipliers accepted from the command line. Beyond these a frame would last
+// days or take less than the time it takes to run it.
+pub const MIN_SPEED: f64 = 0.01;
+pub const MAX_SPEED: f64 = 100.0;
+
+// How fast emulated time runs compared to real time. Fast forward runs more
+// frames per second rather than more instructions per frame, so timers keep
+// their relation to the program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed{
+    Scaled(f64),
+    Uncapped,
+}
+
+impl Speed{
+    pub const NORMAL: Speed = Speed::Scaled(1.0);
+    pub const PRESETS: [Speed; 6] = [
+        Speed::Scaled(0.25),
+        Speed::Scaled(0.5),
+        Speed::NORMAL,
+        Speed::Scaled(2.0),
+        Speed::Scaled(4.0),
+        Speed::Uncapped,
+    ];
+
+    // The next preset up, speeds in between presets snap to the closest one above.
+    pub fn faster(self) -> Self{
+        Self::PRESETS.into_iter().find(|preset| preset.multiplier() > self.multiplier()).unwrap_or(self)
+    }
+
+    pub fn slower(self) -> Self{
+        Self::PRESETS.into_iter().rev().find(|preset| preset.multiplier() < self.multiplier()).unwrap_or(self)
+    }
+
+    fn multiplier(&self) -> f64{
+        match self{
+            Speed::Scaled(multiplier) => *multiplier,
+            Speed::Uncapped => f64::INFINITY,
+        }
+    }
+}
+
+impl fmt::Display for Speed{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            Speed::Scaled(multiplier) => write!(f, "{multiplier}x"),
+            Speed::Uncapped => write!(f, "uncapped"),
+        }
+    }
+}
+
+impl FromStr for Speed{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        if s == "uncapped"{
+            return Ok(Speed::Uncapped);
+        }
+        match s.trim_end_matches('x').parse::<f64>(){
+            Ok(multiplier) if (MIN_SPEED..=MAX_SPEED).contains(&multiplier) => Ok(Speed::Scaled(multiplier)),
+            Ok(_) => Err(format!("speed `{s}` is out of range, expected a multiplier from {MIN_SPEED} to {MAX_SPEED}, or uncapped")),
+            Err(_) => Err(format!("unknown speed `{s}`, expected a multiplier like 0.5 or 2, or uncapped")),
+        }
+    }
+}
+
+// Runs the Cpu in 60 Hz frames: a fixed number of instructions followed by one
+// timer decrement, with the frontend sleeping until `next_frame` in between.
+pub struct Scheduler{
+    instructions_per_frame: u32,
+    frame_duration: Duration,
+    next_frame: Instant,
+    speed: Speed,
+    paused: bool,
+    pending_steps: u32,
+    frames_run: u64,
+    display_wait: bool,
+}
+
+impl Scheduler{
+    pub fn new(instructions_per_frame: u32) -> Self{
+        let frame_duration = Duration::from_secs_f64(1.0 / FRAMES_PER_SECOND as f64);
+        Self{
+            instructions_per_frame,
+            frame_duration,
+            next_frame: Instant::now() + frame_duration,
+            speed: Speed::NORMAL,
+            paused: false,
+            pending_steps: 0,
+            frames_run: 0,
+            display_wait: false,
+        }
+    }
+
+    pub fn display_wait(&self) -> bool{
+        self.display_wait
+    }
+
+    // The COSMAC VIP interpreter waited for vertical blank before drawing, so
+    // at most one sprite was drawn per frame. With this set a DXYN that isn't
+    // the first instruction of the frame ends the frame early and runs at the
+    // start of the next one.
+    pub fn set_display_wait(&mut self, display_wait: bool){
+        self.display_wait = display_wait;
+    }
+
+    pub fn speed(&self) -> Speed{
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed){
+        self.speed = speed;
+        if let Speed::Scaled(multiplier) = speed{
+            self.frame_duration = Duration::from_secs_f64(1.0 / (FRAMES_PER_SECOND as f64 * multiplier));
+        }
+        self.next_frame = Instant::now() + self.frame_duration;
+    }
+
+    pub fn is_paused(&self) -> bool{
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool){
+        self.paused = paused;
+        self.pending_steps = 0;
+        // Resuming shouldn't try to catch up on the time spent paused.
+        self.next_frame = Instant::now() + self.frame_duration;
+    }
+
+    // Pauses if needed and lets exactly one more frame run.
+    pub fn advance_frame(&mut self){
+        if !self.paused{
+            self.set_paused(true);
+        }
+        self.pending_steps += 1;
+    }
+
+    // Frames run so far, for measuring the actual speed.
+    pub fn frames_run(&self) -> u64{
+        self.frames_run
+    }
+
+    pub fn instructions_per_frame(&self) -> u32{
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32){
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    // When the next frame is due, for `ControlFlow::WaitUntil`, and `now` when
+    // uncapped. None while paused or halted, when only an input event can change
+    // anything. Waiting for a key is the same when uncapped, at normal speeds
+    // frames keep coming so the timers run down.
+    pub fn next_frame(&self, cpu: &Cpu, halted: bool) -> Option<Instant>{
+        match (self.paused, self.speed){
+            (true, _) => None,
+            _ if halted => None,
+            (false, Speed::Uncapped) if cpu.is_waiting_for_key() => None,
+            (false, Speed::Uncapped) => Some(Instant::now()),
+            (false, Speed::Scaled(_)) => Some(self.next_frame),
+        }
+    }
+
+    // Number of frames that should run now: 0 when early, more than 1 when
+    // catching up after the frontend was held up. Uncapped speed has no limit,
+    // the frontend stops once it has spent a host frame running them.
+    pub fn frames_due(&mut self, now: Instant) -> u32{
+        if self.paused{
+            return std::mem::take(&mut self.pending_steps);
+        }
+        if self.speed == Speed::Uncapped{
+            return u32::MAX;
+        }
+        if now < self.next_frame{
+            return 0;
+        }
+        let behind = now.duration_since(self.next_frame).as_secs_f64() / self.frame_duration.as_secs_f64();
+        let due = behind as u32 + 1;
+        if due > MAX_CATCH_UP_FRAMES{
+            self.next_frame = now + self.frame_duration;
+            return MAX_CATCH_UP_FRAMES;
+        }
+        self.next_frame += self.frame_duration * due;
+        due
+    }
+
+    // Runs one frame worth of instructions, stopping early if the program waits
+    // for a key or exits, then ticks the timers.
+    pub fn run_frame<B: Backend>(&mut self, cpu: &mut Cpu, backend: &mut B) -> Result<StepOutcome, CpuFault>{
+        self.frames_run += 1;
+        let mut outcome = StepOutcome::Idle;
+        for step in 0..self.instructions_per_frame{
+            if self.display_wait && step > 0 && matches!(cpu.next_op(), Some(Op::Draw{..})){
+                break;
+            }
+            outcome = cpu.step(backend)?;
+            match outcome{
+                StepOutcome::Exited => return Ok(outcome),
+                StepOutcome::WaitingForKey => break,
+                _ => (),
+            }
+        }
+        cpu.tick_timers();
+        backend.set_beep(cpu.sound_active());
+        Ok(outcome)
+    }
+}
+
+impl Default for Scheduler{
+    fn default() -> Self{
+        Self::new(DEFAULT_INSTRUCTIONS_PER_FRAME)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::backend::headless_backend::HeadlessBackend;
+
+    fn waiting_cpu() -> Cpu{
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes(&[0xF0, 0x0A]).unwrap();
+        cpu.step(&mut HeadlessBackend::new()).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn parse_speed(){
+        assert_eq!("2".parse(), Ok(Speed::Scaled(2.0)));
+        assert_eq!("0.5x".parse(), Ok(Speed::Scaled(0.5)));
+        assert_eq!("uncapped".parse(), Ok(Speed::Uncapped));
+        for speed in ["0", "-1", "1e-30", "0.001", "101", "inf", "NaN", "fast"]{
+            assert!(speed.parse::<Speed>().is_err(), "{speed}");
+        }
+    }
+
+    #[test]
+    fn no_frames_while_halted_or_paused(){
+        let cpu = Cpu::new();
+        let mut scheduler = Scheduler::default();
+        assert!(scheduler.next_frame(&cpu, false).is_some());
+        assert!(scheduler.next_frame(&cpu, true).is_none());
+        scheduler.set_speed(Speed::Uncapped);
+        assert!(scheduler.next_frame(&cpu, true).is_none());
+        scheduler.set_paused(true);
+        assert!(scheduler.next_frame(&cpu, false).is_none());
+    }
+
+    #[test]
+    fn key_wait_only_stops_uncapped_frames(){
+        let cpu = waiting_cpu();
+        let mut scheduler = Scheduler::default();
+        assert!(scheduler.next_frame(&cpu, false).is_some());
+        scheduler.set_speed(Speed::Uncapped);
+        assert!(scheduler.next_frame(&cpu, false).is_none());
+    }
+
+    #[test]
+    fn frame_stops_at_key_wait(){
+        let mut cpu = Cpu::new();
+        // V0 = 0x10, DT = V0, then F10A waits before the last instruction.
+        cpu.load_rom_bytes(&[0x60, 0x10, 0xF0, 0x15, 0xF1, 0x0A, 0x61, 0x01]).unwrap();
+        let mut scheduler = Scheduler::default();
+        let outcome = scheduler.run_frame(&mut cpu, &mut HeadlessBackend::new());
+        assert_eq!(outcome, Ok(StepOutcome::WaitingForKey));
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(cpu.dt(), 0x0F);
+    }
+
+    #[test]
+    fn display_wait_draws_once_per_frame(){
+        // I = 0x208, two draws, then a jump to itself.
+        let program = [0xA2, 0x08, 0xD0, 0x01, 0xD0, 0x01, 0x12, 0x06, 0xFF];
+        let mut backend = HeadlessBackend::new();
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes(&program).unwrap();
+        let mut scheduler = Scheduler::new(3);
+        scheduler.run_frame(&mut cpu, &mut backend).unwrap();
+        assert_eq!(cpu.pc(), 0x206);
+
+        // A draw only runs as the first instruction of a frame.
+        let mut cpu = Cpu::new();
+        cpu.load_rom_bytes(&program).unwrap();
+        scheduler.set_display_wait(true);
+        scheduler.run_frame(&mut cpu, &mut backend).unwrap();
+        assert_eq!(cpu.pc(), 0x202);
+        scheduler.run_frame(&mut cpu, &mut backend).unwrap();
+        assert_eq!(cpu.pc(), 0x204);
+    }
+}
@@ -0,0 +1,346 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::display::{HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH};
+use crate::chip8::error::SnapshotError;
+use crate::chip8::font::FontSlot;
+use crate::chip8::quirks::{LoadStoreQuirk, Quirks};
+
+const MAGIC: &[u8; 4] = b"P8SS";
+// Bumped whenever the layout changes.
+pub const SNAPSHOT_VERSION: u16 = 3;
+const NO_KEY_REGISTER: u8 = 0xFF;
+
+// Everything needed to resume a Cpu exactly where it was. Only produced by
+// Cpu::snapshot or a validated from_bytes, so restoring one cannot fail.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Snapshot{
+    pub(crate) quirks: Quirks,
+    pub(crate) memory: Vec<u8>,
+    pub(crate) display_width: usize,
+    pub(crate) display_height: usize,
+    pub(crate) display_pixels: Vec<u8>,
+    pub(crate) planes: u8,
+    pub(crate) small_font: FontSlot,
+    pub(crate) big_font: FontSlot,
+    pub(crate) registers: [u8; 16],
+    pub(crate) pc: u16,
+    pub(crate) i: u16,
+    pub(crate) dt: u8,
+    pub(crate) st: u8,
+    pub(crate) register_to_save_key: Option<u8>,
+    pub(crate) rpl_flags: [u8; 16],
+    pub(crate) audio_pattern: Option<[u8; 16]>,
+    pub(crate) pitch: u8,
+    pub(crate) exited: bool,
+    pub(crate) stack: [u16; 16],
+    pub(crate) sp: usize,
+}
+
+impl Snapshot{
+    pub fn to_bytes(&self) -> Vec<u8>{
+        let mut bytes = Vec::with_capacity(self.memory.len() + self.display_pixels.len() + 128);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+
+        let load_store = match self.quirks.load_store{
+            LoadStoreQuirk::Unchanged => 0,
+            LoadStoreQuirk::IncrementByX => 1,
+            LoadStoreQuirk::IncrementByXPlusOne => 2,
+        };
+        bytes.extend_from_slice(&[
+            self.quirks.shift_uses_vy as u8,
+            load_store,
+            self.quirks.jump_uses_vx as u8,
+            self.quirks.logic_resets_vf as u8,
+            self.quirks.clip_sprites as u8,
+            self.quirks.extended_memory as u8,
+            self.quirks.schip_opcodes as u8,
+            self.quirks.xo_chip_opcodes as u8,
+        ]);
+
+        bytes.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.memory);
+        bytes.extend_from_slice(&(self.display_width as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.display_height as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.display_pixels);
+        bytes.push(self.planes);
+
+        for font in [self.small_font, self.big_font]{
+            bytes.extend_from_slice(&font.address.to_le_bytes());
+            bytes.extend_from_slice(&font.glyph_len.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&self.registers);
+        bytes.extend_from_slice(&self.pc.to_le_bytes());
+        bytes.extend_from_slice(&self.i.to_le_bytes());
+        bytes.push(self.dt);
+        bytes.push(self.st);
+        bytes.push(self.register_to_save_key.unwrap_or(NO_KEY_REGISTER));
+        bytes.extend_from_slice(&self.rpl_flags);
+        bytes.push(self.audio_pattern.is_some() as u8);
+        bytes.extend_from_slice(&self.audio_pattern.unwrap_or_default());
+        bytes.push(self.pitch);
+        bytes.push(self.exited as u8);
+
+        for address in self.stack{
+            bytes.extend_from_slice(&address.to_le_bytes());
+        }
+        bytes.push(self.sp as u8);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError>{
+        let mut reader = Reader{bytes, position: 0};
+        if reader.take(4)? != MAGIC{
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != SNAPSHOT_VERSION{
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let shift_uses_vy = reader.bool()?;
+        let load_store = match reader.u8()?{
+            0 => LoadStoreQuirk::Unchanged,
+            1 => LoadStoreQuirk::IncrementByX,
+            2 => LoadStoreQuirk::IncrementByXPlusOne,
+            _ => return Err(SnapshotError::Invalid("load/store quirk")),
+        };
+        let quirks = Quirks{
+            shift_uses_vy,
+            load_store,
+            jump_uses_vx: reader.bool()?,
+            logic_resets_vf: reader.bool()?,
+            clip_sprites: reader.bool()?,
+            extended_memory: reader.bool()?,
+            schip_opcodes: reader.bool()?,
+            xo_chip_opcodes: reader.bool()?,
+        };
+
+        let memory_len = reader.u32()? as usize;
+        if memory_len != quirks.memory_size(){
+            return Err(SnapshotError::Invalid("memory size"));
+        }
+        let memory = reader.take(memory_len)?.to_vec();
+
+        let display_width = reader.u16()? as usize;
+        let display_height = reader.u16()? as usize;
+        let valid_size = [(LORES_WIDTH, LORES_HEIGHT), (HIRES_WIDTH, HIRES_HEIGHT)].contains(&(display_width, display_height));
+        if !valid_size{
+            return Err(SnapshotError::Invalid("display size"));
+        }
+        let display_pixels = reader.take(display_width * display_height)?.to_vec();
+        let planes = reader.u8()?;
+
+        let small_font = FontSlot{address: reader.u16()?, glyph_len: reader.u16()?};
+        let big_font = FontSlot{address: reader.u16()?, glyph_len: reader.u16()?};
+
+        let registers = reader.array()?;
+        let pc = reader.u16()?;
+        let i = reader.u16()?;
+        let dt = reader.u8()?;
+        let st = reader.u8()?;
+        let register_to_save_key = match reader.u8()?{
+            NO_KEY_REGISTER => None,
+            register if register < 16 => Some(register),
+            _ => return Err(SnapshotError::Invalid("key register")),
+        };
+        let rpl_flags = reader.array()?;
+        let has_audio_pattern = reader.bool()?;
+        let audio_pattern = reader.array()?;
+        let audio_pattern = has_audio_pattern.then_some(audio_pattern);
+        let pitch = reader.u8()?;
+        let exited = reader.bool()?;
+
+        let mut stack = [0; 16];
+        for address in stack.iter_mut(){
+            *address = reader.u16()?;
+        }
+        let sp = reader.u8()? as usize;
+        if sp > stack.len(){
+            return Err(SnapshotError::Invalid("stack pointer"));
+        }
+        if reader.position != bytes.len(){
+            return Err(SnapshotError::TrailingBytes(bytes.len() - reader.position));
+        }
+
+        Ok(Self{
+            quirks,
+            memory,
+            display_width,
+            display_height,
+            display_pixels,
+            planes,
+            small_font,
+            big_font,
+            registers,
+            pc,
+            i,
+            dt,
+            st,
+            register_to_save_key,
+            rpl_flags,
+            audio_pattern,
+            pitch,
+            exited,
+            stack,
+            sp,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError>{
+        fs::write(path, self.to_bytes()).map_err(SnapshotError::Io)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError>{
+        let bytes = fs::read(path).map_err(SnapshotError::Io)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+struct Reader<'a>{
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a>{
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError>{
+        let bytes = self.bytes.get(self.position..self.position + len).ok_or(SnapshotError::Truncated)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError>{
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError>{
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, SnapshotError>{
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError>{
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError>{
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+// Numbered save slots stored next to the ROM as `<rom>.state<N>`.
+pub struct SaveSlots{
+    rom_path: PathBuf,
+    slot: u8,
+}
+
+pub const SAVE_SLOT_COUNT: u8 = 10;
+
+impl SaveSlots{
+    pub fn new<P: AsRef<Path>>(rom_path: P) -> Self{
+        Self{
+            rom_path: rom_path.as_ref().to_path_buf(),
+            slot: 1,
+        }
+    }
+
+    pub fn slot(&self) -> u8{
+        self.slot
+    }
+
+    pub fn next_slot(&mut self){
+        self.slot = (self.slot + 1) % SAVE_SLOT_COUNT;
+    }
+
+    pub fn previous_slot(&mut self){
+        self.slot = (self.slot + SAVE_SLOT_COUNT - 1) % SAVE_SLOT_COUNT;
+    }
+
+    pub fn path(&self, slot: u8) -> PathBuf{
+        self.rom_path.with_extension(format!("state{slot}"))
+    }
+
+    pub fn save(&self, snapshot: &Snapshot) -> Result<PathBuf, SnapshotError>{
+        let path = self.path(self.slot);
+        snapshot.save(&path)?;
+        Ok(path)
+    }
+
+    pub fn load(&self) -> Result<Snapshot, SnapshotError>{
+        Snapshot::load(self.path(self.slot))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::backend::headless_backend::HeadlessBackend;
+    use crate::chip8::cpu::Cpu;
+
+    fn running_snapshot(quirks: Quirks) -> Snapshot{
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.load_rom_from_path("programs/IBM_Logo.ch8").unwrap();
+        let mut backend = HeadlessBackend::new();
+        for _ in 0..20{
+            cpu.step(&mut backend).unwrap();
+        }
+        cpu.tick_timers();
+        cpu.snapshot()
+    }
+
+    #[test]
+    fn round_trip(){
+        for quirks in [Quirks::cosmac_vip(), Quirks::modern(), Quirks::xochip()]{
+            let snapshot = running_snapshot(quirks);
+            let restored = Snapshot::from_bytes(&snapshot.to_bytes()).ok().unwrap();
+            assert!(restored == snapshot);
+
+            let mut cpu = Cpu::new();
+            cpu.restore(&restored);
+            assert!(cpu.snapshot() == snapshot);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_magic(){
+        let mut bytes = running_snapshot(Quirks::modern()).to_bytes();
+        bytes[0] = b'X';
+        assert!(matches!(Snapshot::from_bytes(&bytes), Err(SnapshotError::BadMagic)));
+    }
+
+    #[test]
+    fn rejects_other_versions(){
+        let mut bytes = running_snapshot(Quirks::modern()).to_bytes();
+        bytes[4..6].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        let result = Snapshot::from_bytes(&bytes);
+        assert!(matches!(result, Err(SnapshotError::UnsupportedVersion(version)) if version == SNAPSHOT_VERSION + 1));
+    }
+
+    #[test]
+    fn rejects_the_previous_layout(){
+        // Version 2 had no XO-CHIP opcode quirk, the byte after the SCHIP one.
+        let mut bytes = running_snapshot(Quirks::modern()).to_bytes();
+        bytes.remove(6 + 7);
+        bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(Snapshot::from_bytes(&bytes), Err(SnapshotError::UnsupportedVersion(2))));
+    }
+
+    #[test]
+    fn rejects_trailing_bytes(){
+        let mut bytes = running_snapshot(Quirks::modern()).to_bytes();
+        bytes.extend([0, 0]);
+        assert!(matches!(Snapshot::from_bytes(&bytes), Err(SnapshotError::TrailingBytes(2))));
+    }
+
+    #[test]
+    fn rejects_truncated(){
+        let bytes = running_snapshot(Quirks::modern()).to_bytes();
+        for len in [0, 3, 6, 100, bytes.len() - 1]{
+            assert!(matches!(Snapshot::from_bytes(&bytes[..len]), Err(SnapshotError::Truncated)), "{len} bytes");
+        }
+    }
+}
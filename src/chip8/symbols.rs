@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Label addresses written by the assembler next to a ROM as `<rom>.sym`, one
+// `ADDR name` pair per line with ADDR in hex.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable{
+    by_name: BTreeMap<String, u16>,
+}
+
+impl SymbolTable{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: &str, address: u16){
+        self.by_name.insert(name.to_string(), address);
+    }
+
+    pub fn len(&self) -> usize{
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.by_name.is_empty()
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16>{
+        self.by_name.get(name).copied()
+    }
+
+    // Names an address relative to the closest label at or before it, e.g. `main+4`.
+    pub fn describe(&self, address: u16) -> Option<String>{
+        let (name, base) = self.by_name.iter()
+            .filter(|(_, &base)| base <= address)
+            .max_by_key(|(_, &base)| base)?;
+        match address - base{
+            0 => Some(name.clone()),
+            offset => Some(format!("{name}+{offset}")),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String>{
+        let mut table = Self::new();
+        for (idx, line) in text.lines().enumerate(){
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#'){
+                continue;
+            }
+            let parsed = line.split_once(char::is_whitespace).and_then(|(address, name)| {
+                let address = u16::from_str_radix(address.trim_start_matches("0x"), 16).ok()?;
+                Some((address, name.trim()))
+            });
+            match parsed{
+                Some((address, name)) => table.insert(name, address),
+                None => return Err(format!("line {}: expected `ADDR name`", idx + 1)),
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String>{
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("could not read `{}`: {err}", path.display()))?;
+        Self::parse(&text).map_err(|err| format!("`{}` {err}", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()>{
+        fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for SymbolTable{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        let mut by_address: Vec<(&u16, &String)> = self.by_name.iter().map(|(name, address)| (address, name)).collect();
+        by_address.sort();
+        for (address, name) in by_address{
+            writeln!(f, "{address:03X} {name}")?;
+        }
+        Ok(())
+    }
+}
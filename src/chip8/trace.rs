@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::chip8::disasm::disassemble;
+use crate::chip8::error::CpuFault;
+use crate::chip8::op::{Instruction, Op};
+
+// A million entries is a few seconds of execution at the fastest speeds.
+pub const MAX_TRACE_RING: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpClass{
+    Flow,
+    Skip,
+    Alu,
+    Memory,
+    Display,
+    Input,
+    Timer,
+    Audio,
+}
+
+impl OpClass{
+    pub const ALL: [OpClass; 8] = [
+        OpClass::Flow,
+        OpClass::Skip,
+        OpClass::Alu,
+        OpClass::Memory,
+        OpClass::Display,
+        OpClass::Input,
+        OpClass::Timer,
+        OpClass::Audio,
+    ];
+
+    pub fn name(&self) -> &'static str{
+        match self{
+            OpClass::Flow => "flow",
+            OpClass::Skip => "skip",
+            OpClass::Alu => "alu",
+            OpClass::Memory => "memory",
+            OpClass::Display => "display",
+            OpClass::Input => "input",
+            OpClass::Timer => "timer",
+            OpClass::Audio => "audio",
+        }
+    }
+
+    pub fn of(op: &Op) -> Self{
+        match op{
+            Op::Jump(_) | Op::Call(_) | Op::Return | Op::JumpOffset{..} | Op::Exit => OpClass::Flow,
+            Op::SkipIfEqualByte{..} | Op::SkipIfNotEqualByte{..} | Op::SkipIfEqual{..} | Op::SkipIfNotEqual{..} => OpClass::Skip,
+            Op::SetByte{..}
+            | Op::AddByte{..}
+            | Op::Set{..}
+            | Op::Or{..}
+            | Op::And{..}
+            | Op::Xor{..}
+            | Op::Add{..}
+            | Op::Sub{..}
+            | Op::ShiftRight{..}
+            | Op::SubReverse{..}
+            | Op::ShiftLeft{..}
+            | Op::Random{..} => OpClass::Alu,
+            Op::SetIndex(_)
+            | Op::AddIndex{..}
+            | Op::LongLoad
+            | Op::SaveRange{..}
+            | Op::LoadRange{..}
+            | Op::Store{..}
+            | Op::Load{..}
+            | Op::Bcd{..}
+            | Op::SmallGlyph{..}
+            | Op::BigGlyph{..}
+            | Op::StoreFlags{..}
+            | Op::LoadFlags{..} => OpClass::Memory,
+            Op::Clear
+            | Op::ScrollDown(_)
+            | Op::ScrollUp(_)
+            | Op::ScrollRight
+            | Op::ScrollLeft
+            | Op::Lores
+            | Op::Hires
+            | Op::Draw{..}
+            | Op::SelectPlanes(_) => OpClass::Display,
+            Op::SkipIfKey{..} | Op::SkipIfNotKey{..} | Op::WaitKey{..} => OpClass::Input,
+            Op::GetDelay{..} | Op::SetDelay{..} | Op::SetSound{..} => OpClass::Timer,
+            Op::LoadAudio | Op::SetPitch{..} => OpClass::Audio,
+        }
+    }
+}
+
+impl FromStr for OpClass{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        OpClass::ALL
+            .into_iter()
+            .find(|class| class.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = OpClass::ALL.iter().map(|class| class.name()).collect();
+                format!("unknown opcode class `{s}`, expected one of: {}", names.join(", "))
+            })
+    }
+}
+
+// Which instructions get traced. Invalid opcodes have no class and are
+// traced whenever their PC passes the range filter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter{
+    pub pc_range: Option<RangeInclusive<u16>>,
+    pub classes: Option<Vec<OpClass>>,
+}
+
+impl TraceFilter{
+    pub fn matches(&self, pc: u16, op: Option<&Op>) -> bool{
+        let in_range = self.pc_range.as_ref().is_none_or(|range| range.contains(&pc));
+        let in_class = match (&self.classes, op){
+            (Some(classes), Some(op)) => classes.contains(&OpClass::of(op)),
+            _ => true,
+        };
+        in_range && in_class
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry{
+    pub pc: u16,
+    pub opcode: u16,
+    // (register, old value, new value) for every register the instruction changed.
+    pub changed: Vec<(u8, u8, u8)>,
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+}
+
+impl fmt::Display for TraceEntry{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        let text = disassemble(self.opcode).to_string();
+        write!(f, "{:03X}: {:04X}  {text:<24} i={:03X} dt={:02X} st={:02X}", self.pc, self.opcode, self.i, self.dt, self.st)?;
+        for (register, old, new) in &self.changed{
+            write!(f, "  v{register:x} {old:02X}->{new:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+enum TraceMode{
+    // Every traced instruction is written out as it executes.
+    Stream,
+    // Only the last `capacity` instructions are kept, and written out on a fault.
+    Ring{
+        entries: VecDeque<TraceEntry>,
+        capacity: usize,
+    },
+}
+
+pub struct Tracer{
+    filter: TraceFilter,
+    mode: TraceMode,
+    output: Box<dyn Write>,
+}
+
+impl Tracer{
+    pub fn stream(output: Box<dyn Write>, filter: TraceFilter) -> Self{
+        Self{filter, mode: TraceMode::Stream, output}
+    }
+
+    pub fn ring(capacity: usize, output: Box<dyn Write>, filter: TraceFilter) -> Self{
+        let mode = TraceMode::Ring{
+            // Entries are allocated as they come in, the capacity is only a limit.
+            entries: VecDeque::new(),
+            capacity,
+        };
+        Self{filter, mode, output}
+    }
+
+    pub fn stderr(filter: TraceFilter) -> Self{
+        Self::stream(Box::new(io::stderr()), filter)
+    }
+
+    pub fn record(&mut self, entry: TraceEntry){
+        let op = Instruction::from(entry.opcode).decode().ok();
+        if !self.filter.matches(entry.pc, op.as_ref()){
+            return;
+        }
+        match &mut self.mode{
+            TraceMode::Stream => {
+                // Tracing is best effort, a full disk shouldn't stop the emulator.
+                writeln!(self.output, "{entry}").ok();
+            },
+            TraceMode::Ring{capacity: 0, ..} => (),
+            TraceMode::Ring{entries, capacity} => {
+                if entries.len() == *capacity{
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            },
+        }
+    }
+
+    pub fn fault(&mut self, fault: &CpuFault){
+        if let TraceMode::Ring{entries, ..} = &mut self.mode{
+            writeln!(self.output, "last {} instructions before {fault}:", entries.len()).ok();
+            for entry in entries.drain(..){
+                writeln!(self.output, "{entry}").ok();
+            }
+        }
+        self.output.flush().ok();
+    }
+}
+
+impl Drop for Tracer{
+    fn drop(&mut self){
+        self.output.flush().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn entry(pc: u16) -> TraceEntry{
+        TraceEntry{pc, opcode: 0x6000, changed: Vec::new(), i: 0, dt: 0, st: 0}
+    }
+
+    fn ring_pcs(tracer: &Tracer) -> Vec<u16>{
+        match &tracer.mode{
+            TraceMode::Ring{entries, ..} => entries.iter().map(|entry| entry.pc).collect(),
+            TraceMode::Stream => panic!("not a ring tracer"),
+        }
+    }
+
+    #[test]
+    fn ring_keeps_the_latest(){
+        let mut tracer = Tracer::ring(2, Box::new(io::sink()), TraceFilter::default());
+        for pc in [0x200, 0x202, 0x204]{
+            tracer.record(entry(pc));
+        }
+        assert_eq!(ring_pcs(&tracer), [0x202, 0x204]);
+    }
+
+    #[test]
+    fn empty_ring_keeps_nothing(){
+        let mut tracer = Tracer::ring(0, Box::new(io::sink()), TraceFilter::default());
+        tracer.record(entry(0x200));
+        assert!(ring_pcs(&tracer).is_empty());
+    }
+
+    #[test]
+    fn pc_range_filters(){
+        let filter = TraceFilter{pc_range: Some(0x202..=0x204), classes: None};
+        let mut tracer = Tracer::ring(8, Box::new(io::sink()), filter);
+        for pc in [0x200, 0x202, 0x204, 0x206]{
+            tracer.record(entry(pc));
+        }
+        assert_eq!(ring_pcs(&tracer), [0x202, 0x204]);
+    }
+}
@@ -2,18 +2,268 @@
 
 pub mod chip8;
 pub mod backend;
+pub mod options;
+pub mod repl;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
 
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowAttributes;
 
-use crate::chip8::cpu::Cpu;
-use crate::backend::pixels_backend::PixelsBackend;
+use crate::chip8::assembler::assemble;
+use crate::chip8::cpu::{Cpu, StepOutcome, PROGRAM_START};
+use crate::chip8::disasm::Listing;
+use crate::chip8::font::SCHIP_BIG_FONT;
+use crate::chip8::rewind::RewindBuffer;
+use crate::chip8::scheduler::Scheduler;
+use crate::chip8::snapshot::SaveSlots;
+use crate::chip8::trace::Tracer;
+use crate::backend::audio::{AudioSink, Beeper, WavSink, DEFAULT_SAMPLE_RATE};
+use crate::backend::headless_backend::HeadlessBackend;
+use crate::backend::backend::Backend;
+use crate::backend::filter::FrameFilter;
+use crate::backend::palette::{Palette, PaletteSet};
+use crate::backend::pixels_backend::{Appearance, PixelsBackend};
+use crate::backend::postfx::SoftwarePostFx;
+use crate::options::{AsmOptions, Command, DisasmOptions, Options, USAGE};
 
 
 fn main() {
+    let (options, debug) = match Command::parse(std::env::args().skip(1)){
+        Ok(Command::Run(options)) => (options, false),
+        Ok(Command::Debug(options)) => (options, true),
+        Ok(Command::Disasm(options)) => {
+            if let Err(err) = disassemble_rom(&options){
+                eprintln!("error: {err}");
+                process::exit(1);
+            }
+            return;
+        },
+        Ok(Command::Asm(options)) => {
+            if let Err(err) = assemble_source(&options){
+                eprintln!("error: {err}");
+                process::exit(1);
+            }
+            return;
+        },
+        Ok(Command::Help) => {
+            println!("{USAGE}");
+            return;
+        },
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    let mut cpu = Cpu::with_quirks(options.quirks.quirks());
+    // Options checked that both fonts fit below the program.
+    let big_font_address = options.font_address.checked_add(options.font.data.len() as u16).unwrap();
+    let loaded = cpu.load_font(options.font, options.font_address)
+        .and_then(|_| cpu.load_big_font(&SCHIP_BIG_FONT, big_font_address))
+        .and_then(|_| cpu.load_rom_from_path(&options.rom));
+    if let Err(err) = loaded{
+        eprintln!("error: {err}");
+        process::exit(1);
+    }
+    for warning in cpu.load_warnings(){
+        eprintln!("warning: {warning}");
+    }
+    match create_tracer(&options){
+        Ok(tracer) => cpu.set_tracer(tracer),
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(1);
+        }
+    }
+
+    if debug{
+        repl::run(cpu, &options);
+        return;
+    }
+    if options.headless{
+        run_headless(cpu, &options);
+        return;
+    }
+
+    let palettes = match load_palettes(&options){
+        Ok(palettes) => palettes,
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(1);
+        }
+    };
     let event_loop = EventLoop::new().unwrap();
-    let mut pixels_backend = PixelsBackend::Uninitialized;
+    let mut scheduler = Scheduler::new(options.instructions_per_frame);
+    scheduler.set_speed(options.speed);
+    scheduler.set_display_wait(options.display_wait);
+    let beeper = create_beeper(&options, true);
+    let save_slots = SaveSlots::new(&options.rom);
+    let rewind = RewindBuffer::with_seconds(options.rewind_seconds);
+    let appearance = Appearance{
+        scale: options.scale,
+        fullscreen: options.fullscreen,
+        filter: FrameFilter::new(options.filter, options.filter_frames),
+        palettes,
+        effects: options.postfx,
+        software_postfx: options.software_postfx,
+    };
+    let mut pixels_backend = PixelsBackend::new(cpu, scheduler, beeper, save_slots, rewind, appearance);
 
-    event_loop.set_control_flow(ControlFlow::Poll);
+    event_loop.set_control_flow(ControlFlow::Wait);
     event_loop.run_app(&mut pixels_backend);
 }
+
+fn disassemble_rom(options: &DisasmOptions) -> Result<(), String>{
+    let rom = fs::read(&options.rom)
+        .map_err(|err| format!("could not read ROM file `{}`: {err}", options.rom.display()))?;
+    let listing = Listing::new(&rom, PROGRAM_START as u16, options.syntax).to_string();
+    match &options.output{
+        Some(path) => fs::write(path, listing)
+            .map_err(|err| format!("could not write `{}`: {err}", path.display())),
+        None => {
+            print!("{listing}");
+            Ok(())
+        },
+    }
+}
+
+fn assemble_source(options: &AsmOptions) -> Result<(), String>{
+    let source = fs::read_to_string(&options.source)
+        .map_err(|err| format!("could not read `{}`: {err}", options.source.display()))?;
+    let assembly = assemble(&source).map_err(|err| format!("{}: {err}", options.source.display()))?;
+
+    let output = options.output.clone().unwrap_or_else(|| options.source.with_extension("ch8"));
+    let symbols = output.with_extension("sym");
+    fs::write(&output, &assembly.rom)
+        .map_err(|err| format!("could not write `{}`: {err}", output.display()))?;
+    assembly.symbols.save(&symbols)
+        .map_err(|err| format!("could not write `{}`: {err}", symbols.display()))?;
+    eprintln!("wrote {} bytes to `{}` and {} symbols to `{}`", assembly.rom.len(), output.display(), assembly.symbols.len(), symbols.display());
+    Ok(())
+}
+
+fn run_headless(mut cpu: Cpu, options: &Options){
+    let mut backend = HeadlessBackend::new();
+    backend.beeper = create_beeper(options, false);
+    let mut scheduler = Scheduler::new(options.instructions_per_frame);
+    scheduler.set_display_wait(options.display_wait);
+
+    let mut fault = None;
+    for frame in 0..options.frames as u64{
+        backend.begin_frame(frame);
+        if let Some(key) = backend.take_waited_key(){
+            cpu.waiting_key_pressed(key);
+        }
+
+        let result = scheduler.run_frame(&mut cpu, &mut backend);
+        cpu.present_if_dirty(&mut backend);
+        match result{
+            Ok(StepOutcome::Exited) => break,
+            Ok(_) => (),
+            Err(err) => {
+                fault = Some(err);
+                break;
+            }
+        }
+    }
+
+    print!("{}", backend.screen_to_string());
+    if let Some(path) = &options.screenshot{
+        if let Err(err) = save_screenshot(&backend, options, path){
+            eprintln!("error: {err}");
+        }
+    }
+    if let Some(fault) = fault{
+        eprintln!("halted: {fault}");
+        process::exit(1);
+    }
+}
+
+// Renders the final screen the way the window would, minus the anti-flicker
+// filter, and writes it as a binary PPM.
+fn save_screenshot(backend: &HeadlessBackend, options: &Options, path: &Path) -> Result<(), String>{
+    let palette = load_palettes(options)?.current().clone();
+    let rgba: Vec<u8> = backend.framebuffer.iter().flat_map(|&pixel| palette.colors[pixel as usize & 0x3]).collect();
+    let (image, width, height) = if options.postfx.is_empty(){
+        (rgba, backend.width, backend.height)
+    } else {
+        let (width, height) = SoftwarePostFx::output_size(backend.width, backend.height);
+        let image = SoftwarePostFx::new().render(&rgba, backend.width, backend.height, options.postfx).to_vec();
+        (image, width, height)
+    };
+
+    let mut ppm = format!("P6\n{width} {height}\n255\n").into_bytes();
+    ppm.extend(image.chunks_exact(4).flat_map(|pixel| &pixel[..3]));
+    fs::write(path, ppm).map_err(|err| format!("could not write `{}`: {err}", path.display()))
+}
+
+fn create_tracer(options: &Options) -> Result<Option<Tracer>, String>{
+    if options.trace.is_none() && options.trace_ring.is_none(){
+        return Ok(None);
+    }
+    let output: Box<dyn Write> = match &options.trace{
+        Some(path) if path.as_os_str() != "-" => {
+            let file = File::create(path).map_err(|err| format!("could not create `{}`: {err}", path.display()))?;
+            Box::new(BufWriter::new(file))
+        },
+        _ => Box::new(io::stderr()),
+    };
+    let filter = options.trace_filter.clone();
+    Ok(Some(match options.trace_ring{
+        Some(capacity) => Tracer::ring(capacity, output, filter),
+        None => Tracer::stream(output, filter),
+    }))
+}
+
+fn load_palettes(options: &Options) -> Result<PaletteSet, String>{
+    let extra = match &options.palette_file{
+        Some(path) => Palette::load(path)?,
+        None => Vec::new(),
+    };
+    PaletteSet::new(extra, &options.palette)
+}
+
+fn create_beeper(options: &Options, use_device: bool) -> Option<Beeper>{
+    let sink: Box<dyn AudioSink> = if let Some(path) = &options.wav{
+        match WavSink::create(path, DEFAULT_SAMPLE_RATE){
+            Ok(sink) => Box::new(sink),
+            Err(err) => {
+                eprintln!("warning: could not create `{}`: {err}", path.display());
+                return None;
+            }
+        }
+    } else {
+        open_device_sink(use_device)?
+    };
+
+    let mut beeper = Beeper::new(sink);
+    beeper.wave_mut().set_frequency(options.tone as f32);
+    beeper.wave_mut().set_volume(options.volume as f32 / 100.0);
+    Some(beeper)
+}
+
+#[cfg(feature = "audio")]
+fn open_device_sink(use_device: bool) -> Option<Box<dyn AudioSink>>{
+    if !use_device{
+        return None;
+    }
+    match crate::backend::audio::DeviceSink::open(){
+        Some(sink) => Some(Box::new(sink)),
+        None => {
+            eprintln!("warning: no audio output device available");
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+fn open_device_sink(use_device: bool) -> Option<Box<dyn AudioSink>>{
+    if use_device{
+        eprintln!("warning: built without sound output, rebuild with `--features audio` to hear the beeper");
+    }
+    None
+}
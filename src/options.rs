@@ -0,0 +1,490 @@
+use std::path::PathBuf;
+
+use crate::backend::filter::{FilterMode, DEFAULT_OR_FRAMES, MAX_OR_FRAMES};
+use crate::backend::postfx::Effects;
+use crate::chip8::disasm::Syntax;
+use crate::chip8::cpu::PROGRAM_START;
+use crate::chip8::font::{Font, DEFAULT_FONT_ADDRESS, SCHIP_BIG_FONT};
+use crate::chip8::quirks::QuirkProfile;
+use crate::chip8::rewind::{DEFAULT_REWIND_SECONDS, MAX_REWIND_SECONDS};
+use crate::chip8::scheduler::{Speed, DEFAULT_INSTRUCTIONS_PER_FRAME, FRAMES_PER_SECOND, MAX_INSTRUCTIONS_PER_FRAME};
+use crate::chip8::trace::{OpClass, TraceFilter, MAX_TRACE_RING};
+
+// Already several times the size of any display at the top end.
+const MAX_SCALE: u32 = 64;
+
+pub const USAGE: &str = "\
+usage: pico8 [options] <rom.ch8>
+       pico8 debug [options] <rom.ch8>
+       pico8 disasm [--syntax octo|cowgod] [-o <out>] <rom.ch8>
+       pico8 asm [-o <out.ch8>] <source.8o>
+
+commands:
+    debug           step through the ROM in a terminal debugger, type `help`
+                    at its prompt for the list of debugger commands
+    disasm          list the code reachable from 0x200 with labelled jump and
+                    call targets, everything else as data (default syntax octo)
+    asm             assemble Octo source into a ROM plus a .sym file of its
+                    labels, which the debugger loads when it sits next to the ROM
+
+options:
+    --ipf <n>       instructions executed per 60 Hz frame, up to 10000 (default 11)
+    --ips <n>       instructions executed per second, rounded to whole frames,
+                    up to 600000
+    --speed <x>     initial speed multiplier from 0.01 to 100, e.g. 0.5 or 2, or
+                    uncapped (default 1)
+    --scale <n>     initial window size as a multiple of 64x32, up to 64 (default 10)
+    --fullscreen    start in fullscreen
+    --quirks <name> quirk profile: vip, chip48, schip, xochip or modern
+                    (default modern), SCHIP opcodes need schip, xochip or modern
+                    and XO-CHIP opcodes need xochip
+    --font <name>   small hex font: chip48, vip, dream6800 or eti660 (default chip48)
+    --font-address <addr>
+                    hex address of the small font, the big font follows it and both
+                    must end below 200 (default 050)
+    --display-wait  let sprites draw only at the start of a 60 Hz frame like the
+                    COSMAC VIP did (default on for the vip quirk profile)
+    --filter <name> anti-flicker filter: off, phosphor (fading pixels), blend (the
+                    last two frames mixed) or or (the last few frames combined)
+                    (default off)
+    --filter-frames <n>
+                    number of frames combined by the or filter, up to 16 (default 3)
+    --palette <name>
+                    display colors: classic, amber, green, lcd, high-contrast or one
+                    from --palette-file (default classic)
+    --palette-file <path>
+                    load extra palettes, one per line as a name followed by the
+                    background, plane 1, plane 2 and both-planes colors and an
+                    optional border color, e.g. `sunset #1A1C2C #F4F4F4 #EF7D57 #FFCD75`
+    --postfx <effects>
+                    comma separated post-processing effects: scanlines, grid,
+                    bloom and curvature, crt for scanlines, bloom and curvature,
+                    or none (default none)
+    --postfx-software
+                    apply the effects on the CPU instead of in a shader
+    --headless      run without a window and print the final screen
+    --frames <n>    number of 60 Hz frames to run in headless mode (default 600)
+    --screenshot <path>
+                    in headless mode, also save the final screen as a PPM image
+                    with the palette and post-processing effects applied
+    --tone <hz>     beep frequency (default 440), the beep plays on the default
+                    output device in builds with `--features audio`
+    --volume <n>    beep volume in percent (default 25)
+    --wav <path>    record the beeper output to a WAV file
+    --rewind <s>    seconds of gameplay kept for rewinding, up to 600 (default 10)
+    --trace <path>  log every executed instruction to a file, or to stderr for `-`
+    --trace-ring <n>
+                    keep only the last n traced instructions, up to 1000000,
+                    and write them out when the program faults (to stderr
+                    unless --trace is given)
+    --trace-pc <start>-<end>
+                    only trace instructions in this hex address range
+    --trace-ops <classes>
+                    only trace these comma separated opcode classes: flow, skip,
+                    alu, memory, display, input, timer, audio
+    -h, --help      print this message
+
+hotkeys:
+    F2 / F3         select the previous / next save slot
+    F5 / F9         save / load the selected slot next to the ROM
+    Backspace       hold to rewind
+    F7              cycle through the anti-flicker filters
+    F8              cycle through the palettes
+    F10             toggle the post-processing effects, crt if none were given
+    F11             toggle fullscreen
+    P               pause or resume
+    N               pause and advance a single frame
+    - / =           slower / faster: 1/4, 1/2, 1, 2, 4 times or uncapped
+    0               back to normal speed";
+
+pub enum Command{
+    Run(Options),
+    Debug(Options),
+    Disasm(DisasmOptions),
+    Asm(AsmOptions),
+    Help,
+}
+
+pub struct Options{
+    pub rom: PathBuf,
+    pub instructions_per_frame: u32,
+    pub speed: Speed,
+    pub scale: u32,
+    pub fullscreen: bool,
+    pub quirks: QuirkProfile,
+    pub font: &'static Font,
+    pub font_address: u16,
+    pub display_wait: bool,
+    pub filter: FilterMode,
+    pub filter_frames: usize,
+    pub palette: String,
+    pub palette_file: Option<PathBuf>,
+    pub postfx: Effects,
+    pub software_postfx: bool,
+    pub headless: bool,
+    pub frames: u32,
+    pub screenshot: Option<PathBuf>,
+    pub tone: u32,
+    pub volume: u32,
+    pub wav: Option<PathBuf>,
+    pub rewind_seconds: u32,
+    pub trace: Option<PathBuf>,
+    pub trace_ring: Option<usize>,
+    pub trace_filter: TraceFilter,
+}
+
+impl Command{
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Self, String>{
+        let mut args = args.peekable();
+        let parse_options = match args.peek().map(String::as_str){
+            Some("debug") => {
+                args.next();
+                Command::Debug
+            },
+            Some("disasm") => {
+                args.next();
+                return DisasmOptions::parse(args);
+            },
+            Some("asm") => {
+                args.next();
+                return AsmOptions::parse(args);
+            },
+            _ => Command::Run,
+        };
+        match Options::parse(args)?{
+            Some(options) => Ok(parse_options(options)),
+            None => Ok(Command::Help),
+        }
+    }
+}
+
+impl Options{
+    // Returns None when help was requested.
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Self>, String>{
+        let mut rom = None;
+        let mut instructions_per_frame = DEFAULT_INSTRUCTIONS_PER_FRAME;
+        let mut speed = Speed::NORMAL;
+        let mut scale = 10;
+        let mut fullscreen = false;
+        let mut quirks = QuirkProfile::Modern;
+        let mut font = Font::small_by_name("chip48")?;
+        let mut font_address = DEFAULT_FONT_ADDRESS;
+        let mut display_wait = false;
+        let mut filter = FilterMode::Off;
+        let mut filter_frames = DEFAULT_OR_FRAMES;
+        let mut palette = "classic".to_string();
+        let mut palette_file = None;
+        let mut postfx = Effects::NONE;
+        let mut software_postfx = false;
+        let mut headless = false;
+        let mut frames = 600;
+        let mut screenshot = None;
+        let mut tone = 440;
+        let mut volume = 25;
+        let mut wav = None;
+        let mut rewind_seconds = DEFAULT_REWIND_SECONDS;
+        let mut trace = None;
+        let mut trace_ring = None;
+        let mut trace_filter = TraceFilter::default();
+
+        while let Some(arg) = args.next(){
+            match arg.as_str(){
+                "-h" | "--help" => return Ok(None),
+                "--ipf" => instructions_per_frame = parse_bounded(&arg, args.next(), MAX_INSTRUCTIONS_PER_FRAME)?,
+                "--ips" => {
+                    let max = MAX_INSTRUCTIONS_PER_FRAME * FRAMES_PER_SECOND;
+                    instructions_per_frame = (parse_bounded(&arg, args.next(), max)? / FRAMES_PER_SECOND).max(1);
+                },
+                "--speed" => match args.next(){
+                    Some(value) => speed = value.parse()?,
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--scale" => scale = parse_bounded(&arg, args.next(), MAX_SCALE)?,
+                "--quirks" => match args.next(){
+                    Some(name) => quirks = name.parse()?,
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--font" => match args.next(){
+                    Some(name) => font = Font::small_by_name(&name)?,
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--font-address" => font_address = parse_address(&arg, args.next())?,
+                "--display-wait" => display_wait = true,
+                "--filter" => match args.next(){
+                    Some(name) => filter = name.parse()?,
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--filter-frames" => filter_frames = parse_bounded(&arg, args.next(), MAX_OR_FRAMES)? as usize,
+                "--palette" => match args.next(){
+                    Some(name) => palette = name,
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--palette-file" => match args.next(){
+                    Some(path) => palette_file = Some(PathBuf::from(path)),
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--fullscreen" => fullscreen = true,
+                "--postfx" => match args.next(){
+                    Some(effects) => postfx = effects.parse()?,
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--postfx-software" => software_postfx = true,
+                "--headless" => headless = true,
+                "--screenshot" => match args.next(){
+                    Some(path) => screenshot = Some(PathBuf::from(path)),
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--frames" => frames = parse_number(&arg, args.next())?,
+                "--tone" => tone = parse_number(&arg, args.next())?,
+                "--volume" => volume = parse_number(&arg, args.next())?.min(100),
+                "--wav" => match args.next(){
+                    Some(path) => wav = Some(PathBuf::from(path)),
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--rewind" => rewind_seconds = parse_bounded(&arg, args.next(), MAX_REWIND_SECONDS)?,
+                "--trace" => match args.next(){
+                    Some(path) => trace = Some(PathBuf::from(path)),
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "--trace-ring" => trace_ring = Some(parse_bounded(&arg, args.next(), MAX_TRACE_RING)? as usize),
+                "--trace-pc" => {
+                    let value = args.next();
+                    let Some((start, end)) = value.as_deref().and_then(|range| range.split_once('-')) else {
+                        return Err(format!("`{arg}` expects a hex range like 200-2FF"));
+                    };
+                    let start = parse_address(&arg, Some(start.to_string()))?;
+                    let end = parse_address(&arg, Some(end.to_string()))?;
+                    if start > end{
+                        return Err(format!("`{arg}` range {start:03X}-{end:03X} ends before it starts"));
+                    }
+                    trace_filter.pc_range = Some(start..=end);
+                },
+                "--trace-ops" => match args.next(){
+                    Some(classes) => {
+                        let classes = classes.split(',').map(str::parse).collect::<Result<Vec<OpClass>, String>>()?;
+                        trace_filter.classes = Some(classes);
+                    },
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                _ => {
+                    if rom.is_some(){
+                        return Err(format!("unexpected argument `{arg}`"));
+                    }
+                    rom = Some(PathBuf::from(arg));
+                }
+            }
+        }
+
+        let Some(rom) = rom else {
+            return Err("no ROM file given".to_string());
+        };
+        // Both fonts have to sit below the program.
+        let fonts_end = font_address as usize + font.data.len() + SCHIP_BIG_FONT.data.len();
+        if fonts_end > PROGRAM_START{
+            return Err(format!("`--font-address` {font_address:03X} puts the fonts past the program start at {PROGRAM_START:03X}"));
+        }
+
+        Ok(Some(Options{
+            rom,
+            instructions_per_frame,
+            speed,
+            scale,
+            fullscreen,
+            quirks,
+            font,
+            font_address,
+            display_wait: display_wait || quirks == QuirkProfile::CosmacVip,
+            filter,
+            filter_frames,
+            palette,
+            palette_file,
+            postfx,
+            software_postfx,
+            headless,
+            frames,
+            screenshot,
+            tone,
+            volume,
+            wav,
+            rewind_seconds,
+            trace,
+            trace_ring,
+            trace_filter,
+        }))
+    }
+}
+
+pub struct DisasmOptions{
+    pub rom: PathBuf,
+    pub syntax: Syntax,
+    pub output: Option<PathBuf>,
+}
+
+impl DisasmOptions{
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String>{
+        let mut rom = None;
+        let mut syntax = Syntax::Octo;
+        let mut output = None;
+
+        while let Some(arg) = args.next(){
+            match arg.as_str(){
+                "-h" | "--help" => return Ok(Command::Help),
+                "--syntax" => match args.next(){
+                    Some(name) => syntax = name.parse()?,
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                "-o" | "--output" => match args.next(){
+                    Some(path) => output = Some(PathBuf::from(path)),
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                _ => {
+                    if rom.is_some(){
+                        return Err(format!("unexpected argument `{arg}`"));
+                    }
+                    rom = Some(PathBuf::from(arg));
+                }
+            }
+        }
+
+        let Some(rom) = rom else {
+            return Err("no ROM file given".to_string());
+        };
+        Ok(Command::Disasm(DisasmOptions{rom, syntax, output}))
+    }
+}
+
+pub struct AsmOptions{
+    pub source: PathBuf,
+    pub output: Option<PathBuf>,
+}
+
+impl AsmOptions{
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String>{
+        let mut source = None;
+        let mut output = None;
+
+        while let Some(arg) = args.next(){
+            match arg.as_str(){
+                "-h" | "--help" => return Ok(Command::Help),
+                "-o" | "--output" => match args.next(){
+                    Some(path) => output = Some(PathBuf::from(path)),
+                    None => return Err(format!("`{arg}` expects a value")),
+                },
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                _ => {
+                    if source.is_some(){
+                        return Err(format!("unexpected argument `{arg}`"));
+                    }
+                    source = Some(PathBuf::from(arg));
+                }
+            }
+        }
+
+        let Some(source) = source else {
+            return Err("no source file given".to_string());
+        };
+        Ok(Command::Asm(AsmOptions{source, output}))
+    }
+}
+
+fn parse_number(option: &str, value: Option<String>) -> Result<u32, String>{
+    let Some(value) = value else {
+        return Err(format!("`{option}` expects a value"));
+    };
+    match value.parse(){
+        Ok(0) | Err(_) => Err(format!("`{option}` expects a positive number, got `{value}`")),
+        Ok(number) => Ok(number),
+    }
+}
+
+fn parse_bounded(option: &str, value: Option<String>, max: u32) -> Result<u32, String>{
+    let number = parse_number(option, value)?;
+    if number > max{
+        return Err(format!("`{option}` is at most {max}, got `{number}`"));
+    }
+    Ok(number)
+}
+
+fn parse_address(option: &str, value: Option<String>) -> Result<u16, String>{
+    let Some(value) = value else {
+        return Err(format!("`{option}` expects a value"));
+    };
+    let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16)
+        .map_err(|_| format!("`{option}` expects a hex address, got `{value}`"))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String>{
+        match Command::parse(args.split_whitespace().map(String::from))?{
+            Command::Run(options) => Ok(options),
+            _ => panic!("`{args}` is not a run command"),
+        }
+    }
+
+    #[test]
+    fn trace_options(){
+        let options = parse("--trace-ring 50 --trace-pc 200-2FF rom.ch8").unwrap();
+        assert_eq!(options.trace_ring, Some(50));
+        assert_eq!(options.trace_filter.pc_range, Some(0x200..=0x2FF));
+        assert!(parse("--trace-ring 0 rom.ch8").is_err());
+        assert_eq!(parse("--trace-ring 1000000 rom.ch8").unwrap().trace_ring, Some(1_000_000));
+        let err = parse("--trace-ring 1000001 rom.ch8").err().unwrap();
+        assert_eq!(err, "`--trace-ring` is at most 1000000, got `1000001`");
+        assert!(parse("--trace-pc 300-200 rom.ch8").is_err());
+    }
+
+    #[test]
+    fn vip_waits_for_display(){
+        assert!(parse("--quirks vip rom.ch8").unwrap().display_wait);
+        assert!(!parse("--quirks modern rom.ch8").unwrap().display_wait);
+        assert!(parse("--quirks modern --display-wait rom.ch8").unwrap().display_wait);
+    }
+
+    #[test]
+    fn fonts_end_below_the_program(){
+        // 80 bytes of small font and 160 of big font end right at 0x200.
+        assert_eq!(parse("--font-address 110 rom.ch8").unwrap().font_address, 0x110);
+        let err = parse("--font-address 111 rom.ch8").err().unwrap();
+        assert_eq!(err, "`--font-address` 111 puts the fonts past the program start at 200");
+        assert!(parse("--font-address 200 rom.ch8").is_err());
+        assert!(parse("--font-address FFFF rom.ch8").is_err());
+    }
+
+    #[test]
+    fn scale_bounds(){
+        assert_eq!(parse("--scale 64 rom.ch8").unwrap().scale, 64);
+        assert!(parse("--scale 0 rom.ch8").is_err());
+        assert!(parse("--scale 65 rom.ch8").is_err());
+        assert!(parse("--scale 4294967295 rom.ch8").is_err());
+    }
+
+    #[test]
+    fn speed_bounds(){
+        assert_eq!(parse("--ipf 10000 rom.ch8").unwrap().instructions_per_frame, 10_000);
+        assert_eq!(parse("--ips 600000 rom.ch8").unwrap().instructions_per_frame, 10_000);
+        assert_eq!(parse("--ips 30 rom.ch8").unwrap().instructions_per_frame, 1);
+        let err = parse("--ipf 2000000000 rom.ch8").err().unwrap();
+        assert_eq!(err, "`--ipf` is at most 10000, got `2000000000`");
+        assert!(parse("--ips 600001 rom.ch8").is_err());
+    }
+
+    #[test]
+    fn filter_frames_bounds(){
+        assert_eq!(parse("--filter-frames 16 rom.ch8").unwrap().filter_frames, 16);
+        assert!(parse("--filter-frames 0 rom.ch8").is_err());
+        assert!(parse("--filter-frames 17 rom.ch8").is_err());
+    }
+
+    #[test]
+    fn rewind_bounds(){
+        assert_eq!(parse("--rewind 600 rom.ch8").unwrap().rewind_seconds, 600);
+        assert!(parse("--rewind 0 rom.ch8").is_err());
+        assert!(parse("--rewind 601 rom.ch8").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use std::io::{self, BufRead, Write};
+
+use crate::backend::backend::Keys;
+use crate::backend::headless_backend::HeadlessBackend;
+use crate::chip8::cpu::Cpu;
+use crate::chip8::debugger::{next_opcode, Condition, Debugger, StopReason, WatchKind, Watchpoint};
+use crate::chip8::disasm::disassemble;
+use crate::chip8::symbols::SymbolTable;
+use crate::options::Options;
+
+// Keeps `continue` from hanging the terminal on a program that never stops.
+const CONTINUE_LIMIT: u64 = 10_000_000;
+
+const HELP: &str = "\
+commands:
+    s, step [n]         execute n instructions (default 1)
+    n, next             step over a subroutine call
+    finish              run until the current subroutine returns
+    c, continue [n]     run until something stops execution, or n instructions
+    b, break <addr>     set a breakpoint
+    delete <addr>       remove a breakpoint
+    watch <addr>[-<end>] [r|w|rw]
+                        stop after memory in the range is read or written
+                        (default rw)
+    cond <reg> <op> <value>
+                        stop when a condition becomes true, e.g. `cond v3 == 0x10`
+                        or `cond i >= 0x300`; registers are v0-vf, i, dt and st
+    list                show breakpoints, watchpoints and conditions
+    clear               remove all breakpoints, watchpoints and conditions
+    r, regs             show registers and timers
+    x <addr> [len]      dump memory (default 16 bytes)
+    bt, stack           show the call stack
+    symbols <path>      load a symbol file written by `pico8 asm`
+    screen              print the display
+    key <k>             press hex key k
+    release <k>         release hex key k
+    q, quit             leave the debugger
+an empty line repeats the last command; addresses are hex or label names";
+
+pub fn run(mut cpu: Cpu, options: &Options){
+    let mut backend = HeadlessBackend::new();
+    let mut debugger = Debugger::new(options.instructions_per_frame);
+    let stdin = io::stdin();
+    let mut last_command = String::new();
+    let mut symbols = SymbolTable::new();
+
+    let symbol_path = options.rom.with_extension("sym");
+    if symbol_path.exists(){
+        match SymbolTable::load(&symbol_path){
+            Ok(table) => {
+                println!("loaded {} symbols from `{}`", table.len(), symbol_path.display());
+                symbols = table;
+            },
+            Err(err) => println!("warning: {err}"),
+        }
+    }
+
+    print_location(&cpu, &symbols);
+    loop{
+        print!("(pico8) ");
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line){
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        let line = match line.trim(){
+            "" => last_command.clone(),
+            command => command.to_string(),
+        };
+        last_command.clone_from(&line);
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            continue;
+        };
+        let result = match command{
+            "q" | "quit" => break,
+            "help" | "h" => {
+                println!("{HELP}");
+                Ok(())
+            },
+            "symbols" => expect_arg(args, 0, "a path").and_then(SymbolTable::load).map(|table| {
+                println!("loaded {} symbols", table.len());
+                symbols = table;
+            }),
+            _ => execute(command, args, &mut cpu, &mut backend, &mut debugger, &symbols),
+        };
+        if let Err(err) = result{
+            println!("error: {err}");
+        }
+    }
+}
+
+fn execute(
+    command: &str,
+    args: &[&str],
+    cpu: &mut Cpu,
+    backend: &mut HeadlessBackend,
+    debugger: &mut Debugger,
+    symbols: &SymbolTable,
+) -> Result<(), String>{
+    let parse_address = |value: &str| symbols.address_of(value).map_or_else(|| parse_address(value), Ok);
+    let report = |reason: StopReason, cpu: &Cpu| report(reason, cpu, symbols);
+
+    match command{
+        "s" | "step" => {
+            let count = args.first().map(|count| parse_value(count)).transpose()?.unwrap_or(1);
+            let mut reason = StopReason::Stepped;
+            for _ in 0..count{
+                reason = debugger.step_into(cpu, backend);
+                if reason != StopReason::Stepped{
+                    break;
+                }
+            }
+            report(reason, cpu);
+        },
+        "n" | "next" => report(debugger.step_over(cpu, backend, Some(CONTINUE_LIMIT)), cpu),
+        "finish" => report(debugger.step_out(cpu, backend, Some(CONTINUE_LIMIT)), cpu),
+        "c" | "continue" => {
+            let limit = args.first().map(|count| parse_value(count)).transpose()?.map_or(CONTINUE_LIMIT, u64::from);
+            report(debugger.resume(cpu, backend, Some(limit)), cpu);
+        },
+        "b" | "break" => {
+            let address = parse_address(expect_arg(args, 0, "an address")?)?;
+            if debugger.add_breakpoint(address){
+                println!("breakpoint at {address:03X}");
+            }
+        },
+        "delete" => {
+            let address = parse_address(expect_arg(args, 0, "an address")?)?;
+            if !debugger.remove_breakpoint(address){
+                return Err(format!("no breakpoint at {address:03X}"));
+            }
+        },
+        "watch" => {
+            let range = expect_arg(args, 0, "an address or range")?;
+            let (start, end) = match range.split_once('-'){
+                Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+                None => {
+                    let address = parse_address(range)?;
+                    (address, address)
+                },
+            };
+            if end < start{
+                return Err(format!("`{range}` ends before it starts"));
+            }
+            let kind = match args.get(1).copied(){
+                None | Some("rw") => WatchKind::ReadWrite,
+                Some("r") => WatchKind::Read,
+                Some("w") => WatchKind::Write,
+                Some(kind) => return Err(format!("unknown watch kind `{kind}`, expected r, w or rw")),
+            };
+            let watchpoint = Watchpoint{start, end, kind};
+            debugger.add_watchpoint(watchpoint);
+            println!("watching {watchpoint}");
+        },
+        "cond" => {
+            let [target, comparison, value] = args else {
+                return Err("`cond` expects a register, a comparison and a value".to_string());
+            };
+            let condition = Condition{
+                target: target.parse()?,
+                comparison: comparison.parse()?,
+                value: parse_value(value)?,
+            };
+            debugger.add_condition(condition, cpu);
+            println!("condition {}: {condition}", debugger.conditions().count() - 1);
+        },
+        "list" => {
+            for address in debugger.breakpoints(){
+                println!("breakpoint {address:03X}");
+            }
+            for watchpoint in debugger.watchpoints(){
+                println!("watch {watchpoint}");
+            }
+            for (index, condition) in debugger.conditions().enumerate(){
+                println!("condition {index}: {condition}");
+            }
+        },
+        "clear" => debugger.clear(),
+        "r" | "regs" => print_registers(cpu),
+        "x" => {
+            let start = parse_address(expect_arg(args, 0, "an address")?)? as usize;
+            let len = args.get(1).map(|len| parse_value(len)).transpose()?.unwrap_or(16) as usize;
+            let memory = cpu.memory();
+            let end = (start + len).min(memory.len());
+            for (row, bytes) in memory[start.min(end)..end].chunks(16).enumerate(){
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+                println!("{:03X}: {}", start + row * 16, hex.join(" "));
+            }
+        },
+        "bt" | "stack" => {
+            if cpu.stack().is_empty(){
+                println!("not inside a subroutine");
+            }
+            for (depth, address) in cpu.stack().iter().enumerate().rev(){
+                println!("#{depth} returns to {address:03X}");
+            }
+        },
+        "screen" => {
+            cpu.present_if_dirty(backend);
+            print!("{}", backend.screen_to_string());
+        },
+        "key" | "release" => {
+            let key = parse_key(expect_arg(args, 0, "a hex key")?)?;
+            backend.set_key(key, command == "key");
+            if let Some(key) = backend.take_waited_key(){
+                cpu.waiting_key_pressed(key);
+            }
+        },
+        _ => return Err(format!("unknown command `{command}`, type `help` for a list")),
+    }
+    Ok(())
+}
+
+fn report(reason: StopReason, cpu: &Cpu, symbols: &SymbolTable){
+    if reason != StopReason::Stepped{
+        println!("{reason}");
+    }
+    print_location(cpu, symbols);
+}
+
+fn print_location(cpu: &Cpu, symbols: &SymbolTable){
+    let location = match symbols.describe(cpu.pc()){
+        Some(label) => format!("{:03X} <{label}>", cpu.pc()),
+        None => format!("{:03X}", cpu.pc()),
+    };
+    match next_opcode(cpu){
+        Some(opcode) => println!("{location}: {opcode:04X}  {}", disassemble(opcode)),
+        None => println!("{location}: <out of memory>"),
+    }
+}
+
+fn print_registers(cpu: &Cpu){
+    let registers = cpu.registers();
+    for (row, values) in registers.chunks(8).enumerate(){
+        let line: Vec<String> = values.iter()
+            .enumerate()
+            .map(|(idx, value)| format!("v{:X}={value:02X}", row * 8 + idx))
+            .collect();
+        println!("{}", line.join(" "));
+    }
+    println!("pc={:03X} i={:03X} dt={:02X} st={:02X} sp={}", cpu.pc(), cpu.i(), cpu.dt(), cpu.st(), cpu.stack().len());
+}
+
+fn expect_arg<'a>(args: &[&'a str], idx: usize, what: &str) -> Result<&'a str, String>{
+    args.get(idx).copied().ok_or_else(|| format!("expected {what}"))
+}
+
+fn parse_address(value: &str) -> Result<u16, String>{
+    let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("`{value}` is not a hex address"))
+}
+
+// Values are decimal unless prefixed with 0x.
+fn parse_value(value: &str) -> Result<u16, String>{
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")){
+        Some(digits) => u16::from_str_radix(digits, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("`{value}` is not a number"))
+}
+
+fn parse_key(value: &str) -> Result<Keys, String>{
+    match u8::from_str_radix(value, 16){
+        Ok(key) if key < 16 => Ok(key.into()),
+        _ => Err(format!("`{value}` is not a hex key 0-F")),
+    }
+}